@@ -0,0 +1,210 @@
+use crate::history::HistoryFilter;
+use simplelog::LevelFilter;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: GitHub-Repository-Sync [OPTIONS] [COMMAND]
+
+Commands:
+  run            Keep every repository in sync until stopped (default)
+  once           Check and sync every repository once, then exit
+  status         Show the saved sync state of every repository
+  check-config   Validate the configuration and exit
+  history        Print the sync history [--repo <name>] [--since <date>] [--until <date>]
+
+Options:
+  --config <path>       Configuration file [default: config.toml]
+  --log-level <level>   off, error, warn, info, debug or trace [default: info]
+  --interval <seconds>  Override check_interval_seconds for every repository
+  --non-interactive     Never wait for Enter before exiting on an error
+  -h, --help            Print this help
+
+Exit codes:
+  0  success; for `once`, every repository is up to date or was synced
+  1  runtime failure; for `once`, at least one repository failed to sync
+  2  invalid command line
+  3  `once` only: nothing failed, but at least one update was skipped
+  4  the config file is missing or unreadable
+  5  the config file is invalid";
+
+// Process exit codes, as listed in the usage text.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_SKIPPED: i32 = 3;
+pub const EXIT_CONFIG_MISSING: i32 = 4;
+pub const EXIT_CONFIG_INVALID: i32 = 5;
+
+pub enum Command {
+    Run,
+    Once,
+    Status,
+    CheckConfig,
+    History(HistoryFilter),
+    Help,
+}
+
+pub struct Cli {
+    pub config: PathBuf,
+    pub log_level: Option<LevelFilter>,
+    pub interval: Option<u64>,
+    pub non_interactive: bool,
+    pub command: Command,
+}
+
+impl Cli {
+    // Parse the arguments after the program name. The global options may appear before or
+    // after the command; anything else after the command belongs to it.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut config = PathBuf::from("config.toml");
+        let mut log_level = None;
+        let mut interval = None;
+        let mut non_interactive = false;
+        let mut command = None;
+        let mut command_args = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--config" => config = PathBuf::from(value()?),
+                "--log-level" => {
+                    let level = value()?;
+                    log_level = Some(
+                        level
+                            .parse()
+                            .map_err(|_| format!("unknown log level \"{}\"", level))?,
+                    );
+                }
+                "--interval" => {
+                    let seconds = value()?;
+                    interval = match seconds.parse() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => {
+                            return Err(format!(
+                                "--interval needs a positive number of seconds, got \"{}\"",
+                                seconds
+                            ))
+                        }
+                    };
+                }
+                "--non-interactive" => non_interactive = true,
+                "-h" | "--help" => return Ok(Cli::help()),
+                _ if command.is_none() && !arg.starts_with('-') => command = Some(arg),
+                _ => command_args.push(arg),
+            }
+        }
+
+        let command = match command.as_deref().unwrap_or("run") {
+            "history" => Command::History(HistoryFilter::from_args(&command_args)?),
+            "run" => Command::Run,
+            "once" => Command::Once,
+            "status" => Command::Status,
+            "check-config" => Command::CheckConfig,
+            "help" => Command::Help,
+            other => return Err(format!("unknown command \"{}\"", other)),
+        };
+        // Only `history` takes options of its own
+        if !matches!(command, Command::History(_)) {
+            if let Some(arg) = command_args.first() {
+                return Err(format!("unexpected argument \"{}\"", arg));
+            }
+        }
+        Ok(Cli {
+            config,
+            log_level,
+            interval,
+            non_interactive,
+            command,
+        })
+    }
+
+    // Whether a person is likely watching, so errors can wait for Enter before exiting.
+    pub fn interactive(&self) -> bool {
+        !self.non_interactive && io::stdin().is_terminal()
+    }
+
+    fn help() -> Self {
+        Cli {
+            config: PathBuf::new(),
+            log_level: None,
+            interval: None,
+            non_interactive: true,
+            command: Command::Help,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults_to_run_with_config_toml() {
+        let cli = parse(&[]).unwrap();
+        assert!(matches!(cli.command, Command::Run));
+        assert_eq!(cli.config, PathBuf::from("config.toml"));
+        assert_eq!(cli.log_level, None);
+        assert_eq!(cli.interval, None);
+        assert!(!cli.non_interactive);
+    }
+
+    #[test]
+    fn accepts_options_before_and_after_the_command() {
+        let cli = parse(&[
+            "--config",
+            "/etc/sync.toml",
+            "once",
+            "--log-level",
+            "debug",
+            "--interval",
+            "30",
+            "--non-interactive",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Command::Once));
+        assert_eq!(cli.config, PathBuf::from("/etc/sync.toml"));
+        assert_eq!(cli.log_level, Some(LevelFilter::Debug));
+        assert_eq!(cli.interval, Some(30));
+        assert!(cli.non_interactive);
+    }
+
+    #[test]
+    fn passes_history_options_through() {
+        let cli = parse(&["history", "--repo", "owner/repo", "--since", "2024-01-01"]).unwrap();
+        match cli.command {
+            Command::History(filter) => {
+                assert_eq!(filter.repo.as_deref(), Some("owner/repo"));
+                assert!(filter.since.is_some());
+                assert!(filter.until.is_none());
+            }
+            _ => panic!("expected the history command"),
+        }
+    }
+
+    #[test]
+    fn help_wins_over_everything_else() {
+        assert!(matches!(
+            parse(&["once", "-h"]).unwrap().command,
+            Command::Help
+        ));
+        assert!(matches!(parse(&["--help"]).unwrap().command, Command::Help));
+        assert!(matches!(parse(&["help"]).unwrap().command, Command::Help));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["sync"]).is_err());
+        assert!(parse(&["status", "extra"]).is_err());
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["--interval", "soon"]).is_err());
+        assert!(parse(&["--interval", "0"]).is_err());
+        assert!(parse(&["history", "--since", "yesterday"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+}
@@ -0,0 +1,745 @@
+mod validate;
+
+use log::info;
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Legacy single-repo layout, still accepted and folded into `repos`.
+    github: Option<GitHubConfig>,
+    local_repo: Option<LocalRepoConfig>,
+    #[serde(default)]
+    pub repos: Vec<RepoConfig>,
+    // Where API responses are cached for conditional requests.
+    #[serde(default = "default_cache_file")]
+    pub cache_file: String,
+    // Append-only log with one JSON record per sync.
+    #[serde(default = "default_history_file")]
+    pub history_file: String,
+    // Directory holding one state file per repository.
+    #[serde(default = "default_state_dir")]
+    pub state_dir: String,
+    #[serde(default)]
+    pub http: HttpConfig,
+    pub webhook: Option<WebhookConfig>,
+}
+
+// Embedded receiver for GitHub `push` webhooks. While it is enabled, polling only runs as a
+// slow fallback in case a delivery is missed.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub listen: String,
+    pub secret: String,
+    #[serde(default = "default_webhook_path")]
+    pub path: String,
+    #[serde(default = "default_fallback_interval")]
+    pub fallback_interval_seconds: u64,
+}
+
+// Settings for the HTTP client shared by all repositories.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_seconds: u64,
+    #[serde(default = "default_request_timeout")]
+    pub timeout_seconds: u64,
+    // Proxy for API requests and git fetches. Without it HTTPS_PROXY/NO_PROXY are honored.
+    pub proxy: Option<String>,
+    // PEM file with extra root certificates, e.g. for corporate TLS interception.
+    pub ca_bundle: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout_seconds: default_connect_timeout(),
+            timeout_seconds: default_request_timeout(),
+            proxy: None,
+            ca_bundle: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoConfig {
+    pub github: GitHubConfig,
+    pub local_repo: LocalRepoConfig,
+    // Commands that can veto an update before the working tree is touched.
+    #[serde(default)]
+    pub pre_sync: Vec<HookConfig>,
+    // Commands run in the checkout after it changed.
+    #[serde(default)]
+    pub post_sync: Vec<HookConfig>,
+    // Checked after a sync; on failure the checkout is rolled back.
+    pub health_check: Option<HealthCheckConfig>,
+    // Copied from `[http]` when the config is loaded, for git fetches.
+    #[serde(skip)]
+    pub proxy: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GitHubConfig {
+    #[serde(default)]
+    pub provider: ProviderKind,
+    // Overrides the provider's public API, e.g. for GitHub Enterprise Server or a self-managed
+    // GitLab or Gitea instance.
+    pub api_base_url: Option<String>,
+    // Not used by the `git` provider, which reads the remote from the local checkout.
+    #[serde(default)]
+    pub owner: String,
+    #[serde(default)]
+    pub repo: String,
+    pub target_branch: String,
+    pub access_token: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LocalRepoConfig {
+    pub path: String,
+    pub check_interval_seconds: u64,
+    #[serde(default = "default_remote")]
+    pub remote: String,
+    #[serde(default)]
+    pub strategy: SyncStrategy,
+    #[serde(default)]
+    pub dirty_policy: DirtyPolicy,
+    #[serde(default)]
+    pub branch_policy: BranchPolicy,
+    #[serde(default)]
+    pub clone_if_missing: bool,
+    // Overrides the URL used by `clone_if_missing`, defaulting to the provider's HTTPS URL.
+    pub clone_url: Option<String>,
+}
+
+// A shell command run around a sync.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfig {
+    pub command: String,
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_seconds: u64,
+    #[serde(default)]
+    pub on_failure: HookFailurePolicy,
+}
+
+// How to tell whether a sync left the service healthy: a shell command that must exit zero, or
+// a URL that must answer GET with a success status. Exactly one of the two is set.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheckConfig {
+    pub command: Option<String>,
+    pub url: Option<String>,
+    // Time to give services to come back up after the post-sync hooks before checking.
+    #[serde(default)]
+    pub delay_seconds: u64,
+    #[serde(default = "default_health_check_timeout")]
+    pub timeout_seconds: u64,
+}
+
+// What a failing post-sync hook means for the hooks after it. Pre-sync hooks always stop at
+// the first failure, since that failure vetoes the update.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookFailurePolicy {
+    // Log the failure and run the remaining hooks anyway.
+    #[default]
+    Continue,
+    // Log the failure and skip the remaining hooks.
+    Abort,
+}
+
+// The hosting service whose REST API is polled for the latest commit.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    GitHub,
+    GitLab,
+    Gitea,
+    Bitbucket,
+    // No REST API: list the branch tip straight from the git remote, like `git ls-remote`.
+    Git,
+}
+
+// How remote changes are applied to the local branch.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncStrategy {
+    // Only fast-forward, refusing to touch a branch that has diverged.
+    #[default]
+    FfOnly,
+    // Replay local commits on top of the remote branch.
+    Rebase,
+    // Create a merge commit joining local and remote history.
+    Merge,
+    // Discard local commits and mirror the remote branch exactly.
+    ResetHard,
+}
+
+// What to do when the working tree has local changes to tracked files.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirtyPolicy {
+    // Log the changed paths and skip the sync.
+    #[default]
+    Refuse,
+    // Stash the changes, sync, then re-apply them.
+    Stash,
+    // Throw the changes away before syncing.
+    Discard,
+}
+
+// What to do when the checkout is not on the configured target branch.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BranchPolicy {
+    // Log a warning and skip the sync.
+    #[default]
+    Warn,
+    // Switch to the target branch, creating it from the remote if needed.
+    Checkout,
+}
+
+impl fmt::Display for SyncStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SyncStrategy::FfOnly => "ff-only",
+            SyncStrategy::Rebase => "rebase",
+            SyncStrategy::Merge => "merge",
+            SyncStrategy::ResetHard => "reset-hard",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn default_hook_timeout() -> u64 {
+    300
+}
+
+fn default_health_check_timeout() -> u64 {
+    30
+}
+
+fn default_webhook_path() -> String {
+    "/webhook".to_string()
+}
+
+fn default_fallback_interval() -> u64 {
+    600
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_request_timeout() -> u64 {
+    30
+}
+
+fn default_history_file() -> String {
+    "sync_history.jsonl".to_string()
+}
+
+fn default_state_dir() -> String {
+    "state".to_string()
+}
+
+fn default_cache_file() -> String {
+    "http_cache.json".to_string()
+}
+
+fn default_remote() -> String {
+    "origin".to_string()
+}
+
+impl GitHubConfig {
+    // The API root requests are built from: `api_base_url` if set, otherwise the provider's
+    // public API.
+    pub fn api_base_url(&self) -> Result<Url, String> {
+        let raw = match (&self.api_base_url, self.provider.default_api_url()) {
+            (Some(url), _) => url.as_str(),
+            (None, Some(url)) => url,
+            (None, None) => return Err("the git provider has no REST API".to_string()),
+        };
+        let url = Url::parse(raw)
+            .map_err(|e| format!("api_base_url \"{}\" is not a valid URL: {}", raw, e))?;
+        if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+            return Err(format!(
+                "api_base_url \"{}\" must be an http:// or https:// URL",
+                raw
+            ));
+        }
+        Ok(url)
+    }
+}
+
+impl RepoConfig {
+    // Short label used to tell repos apart in the log.
+    pub fn name(&self) -> String {
+        if self.github.owner.is_empty() || self.github.repo.is_empty() {
+            return format!("{}@{}", self.local_repo.path, self.github.target_branch);
+        }
+        format!(
+            "{}/{}@{}",
+            self.github.owner, self.github.repo, self.github.target_branch
+        )
+    }
+
+    // URL to clone from when the local checkout does not exist yet. Only the provider's public
+    // host is known; the `git` provider and a custom `api_base_url` need an explicit
+    // `clone_url`, so an enterprise token is never offered to the public host.
+    pub fn clone_url(&self) -> Option<String> {
+        if let Some(url) = &self.local_repo.clone_url {
+            return Some(url.clone());
+        }
+        if self.github.api_base_url.is_some() {
+            return None;
+        }
+        self.github
+            .provider
+            .web_url()
+            .map(|web_url| format!("{}/{}/{}.git", web_url, self.github.owner, self.github.repo))
+    }
+}
+
+// Why the configuration could not be loaded. The two cases exit with different codes.
+pub enum ConfigError {
+    // The file could not be read at all.
+    Missing(String),
+    // The file was read but its contents are not a usable configuration.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(message) | ConfigError::Invalid(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+// Load the configuration from `path`. Relative paths inside it are resolved against the
+// file's own directory, so the result does not depend on the working directory.
+pub fn load_config(path: &Path) -> Result<Config, ConfigError> {
+    let config_content = match fs::read_to_string(path) {
+        Ok(content) => {
+            info!("Config file read successfully.");
+            content
+        }
+        Err(e) => {
+            return Err(ConfigError::Missing(format!(
+                "Failed to read {}: {}",
+                path.display(),
+                e
+            )));
+        }
+    };
+
+    let mut config: Config = toml::from_str(&config_content).map_err(|e| {
+        let message = e.to_string();
+        // Point unknown keys at their own line rather than the end of their table
+        let message = match validate::unknown_key_line(&config_content, &message) {
+            Some(line) => format!(
+                "line {}: {}",
+                line,
+                message.split(" at line ").next().unwrap_or(&message)
+            ),
+            None => message,
+        };
+        ConfigError::Invalid(format!("Failed to parse {}: {}", path.display(), message))
+    })?;
+    info!("Config file parsed successfully.");
+
+    let legacy = config.github.is_some() && config.local_repo.is_some();
+    match (config.github.take(), config.local_repo.take()) {
+        (Some(github), Some(local_repo)) => {
+            config.repos.insert(
+                0,
+                RepoConfig {
+                    github,
+                    local_repo,
+                    pre_sync: Vec::new(),
+                    post_sync: Vec::new(),
+                    health_check: None,
+                    proxy: None,
+                },
+            );
+        }
+        (None, None) => {}
+        _ => {
+            return Err(ConfigError::Invalid(
+                "Both [github] and [local_repo] sections are required when not using [[repos]]."
+                    .to_string(),
+            ));
+        }
+    }
+
+    if config.repos.is_empty() {
+        return Err(ConfigError::Invalid(format!(
+            "No repositories configured in {}.",
+            path.display()
+        )));
+    }
+
+    apply_env_overrides(&mut config)
+        .map_err(|e| ConfigError::Invalid(format!("Invalid environment override: {}", e)))?;
+    resolve_secrets(&mut config)
+        .map_err(|e| ConfigError::Invalid(format!("Failed to resolve a secret: {}", e)))?;
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    resolve_path(base, &mut config.cache_file);
+    resolve_path(base, &mut config.history_file);
+    resolve_path(base, &mut config.state_dir);
+    if let Some(ca_bundle) = &mut config.http.ca_bundle {
+        resolve_path(base, ca_bundle);
+    }
+    for repo in &mut config.repos {
+        resolve_path(base, &mut repo.local_repo.path);
+        repo.proxy = config.http.proxy.clone();
+    }
+
+    if let Err(errors) = validate::validate(&config, &config_content, legacy) {
+        return Err(ConfigError::Invalid(format!(
+            "{} has {} problem(s):\n  {}",
+            path.display(),
+            errors.len(),
+            errors.join("\n  ")
+        )));
+    }
+
+    info!("Loaded {} repository configuration(s).", config.repos.len());
+    Ok(config)
+}
+
+// Prefix of the environment variables that override values from the config file.
+const ENV_PREFIX: &str = "REPO_SYNC_";
+
+// Override `field` with the environment variable `REPO_SYNC_<name>`, if it is set. The value is
+// read the way it would be written in config.toml, so numbers and booleans work as expected,
+// and falls back to a plain string.
+fn override_field<T: DeserializeOwned>(name: &str, field: &mut T) -> Result<(), String> {
+    let name = format!("{}{}", ENV_PREFIX, name);
+    let raw = match env::var(&name) {
+        Ok(raw) => raw,
+        Err(_) => return Ok(()),
+    };
+    let as_string = toml::Value::String(raw.clone());
+    let value = toml::from_str::<toml::value::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .and_then(|value| value.try_into().ok());
+    *field = match value {
+        Some(value) => value,
+        None => as_string
+            .try_into()
+            .map_err(|e| format!("{}: {}", name, e))?,
+    };
+    info!("Using {} from the environment.", name);
+    Ok(())
+}
+
+// Whether any of the variables `REPO_SYNC_<prefix><name>` is set.
+fn any_set(prefix: &str, names: &[&str]) -> bool {
+    names
+        .iter()
+        .any(|name| env::var_os(format!("{}{}{}", ENV_PREFIX, prefix, name)).is_some())
+}
+
+// Apply the `REPO_SYNC_*` overrides of one repository, e.g. `REPO_SYNC_GITHUB_TOKEN` or, with
+// `prefix` "REPOS_0_", `REPO_SYNC_REPOS_0_GITHUB_TOKEN`.
+fn override_repo(repo: &mut RepoConfig, prefix: &str) -> Result<(), String> {
+    let github = &mut repo.github;
+    override_field(&format!("{}GITHUB_PROVIDER", prefix), &mut github.provider)?;
+    override_field(
+        &format!("{}GITHUB_API_BASE_URL", prefix),
+        &mut github.api_base_url,
+    )?;
+    override_field(&format!("{}GITHUB_OWNER", prefix), &mut github.owner)?;
+    override_field(&format!("{}GITHUB_REPO", prefix), &mut github.repo)?;
+    override_field(
+        &format!("{}GITHUB_TARGET_BRANCH", prefix),
+        &mut github.target_branch,
+    )?;
+    override_field(
+        &format!("{}GITHUB_ACCESS_TOKEN", prefix),
+        &mut github.access_token,
+    )?;
+    override_field(&format!("{}GITHUB_TOKEN", prefix), &mut github.access_token)?;
+
+    let local_repo = &mut repo.local_repo;
+    override_field(&format!("{}LOCAL_REPO_PATH", prefix), &mut local_repo.path)?;
+    override_field(
+        &format!("{}LOCAL_REPO_CHECK_INTERVAL_SECONDS", prefix),
+        &mut local_repo.check_interval_seconds,
+    )?;
+    override_field(
+        &format!("{}LOCAL_REPO_REMOTE", prefix),
+        &mut local_repo.remote,
+    )?;
+    override_field(
+        &format!("{}LOCAL_REPO_STRATEGY", prefix),
+        &mut local_repo.strategy,
+    )?;
+    override_field(
+        &format!("{}LOCAL_REPO_DIRTY_POLICY", prefix),
+        &mut local_repo.dirty_policy,
+    )?;
+    override_field(
+        &format!("{}LOCAL_REPO_BRANCH_POLICY", prefix),
+        &mut local_repo.branch_policy,
+    )?;
+    override_field(
+        &format!("{}LOCAL_REPO_CLONE_IF_MISSING", prefix),
+        &mut local_repo.clone_if_missing,
+    )?;
+    override_field(
+        &format!("{}LOCAL_REPO_CLONE_URL", prefix),
+        &mut local_repo.clone_url,
+    )?;
+
+    // A health check can be added from the environment alone; validation then makes sure it
+    // got a command or a URL
+    let health_check = [
+        "HEALTH_CHECK_COMMAND",
+        "HEALTH_CHECK_URL",
+        "HEALTH_CHECK_DELAY_SECONDS",
+        "HEALTH_CHECK_TIMEOUT_SECONDS",
+    ];
+    if repo.health_check.is_none() && any_set(prefix, &health_check) {
+        repo.health_check = Some(HealthCheckConfig {
+            command: None,
+            url: None,
+            delay_seconds: 0,
+            timeout_seconds: default_health_check_timeout(),
+        });
+    }
+    if let Some(check) = &mut repo.health_check {
+        override_field(
+            &format!("{}HEALTH_CHECK_COMMAND", prefix),
+            &mut check.command,
+        )?;
+        override_field(&format!("{}HEALTH_CHECK_URL", prefix), &mut check.url)?;
+        override_field(
+            &format!("{}HEALTH_CHECK_DELAY_SECONDS", prefix),
+            &mut check.delay_seconds,
+        )?;
+        override_field(
+            &format!("{}HEALTH_CHECK_TIMEOUT_SECONDS", prefix),
+            &mut check.timeout_seconds,
+        )?;
+    }
+    Ok(())
+}
+
+// Apply the `REPO_SYNC_*` environment variables on top of the config file. Repository settings
+// without an index apply to every repo; `REPO_SYNC_REPOS_<n>_...` targets the n-th one (from 0)
+// and wins over both.
+fn apply_env_overrides(config: &mut Config) -> Result<(), String> {
+    override_field("CACHE_FILE", &mut config.cache_file)?;
+    override_field("STATE_DIR", &mut config.state_dir)?;
+    override_field("HISTORY_FILE", &mut config.history_file)?;
+
+    let http = &mut config.http;
+    override_field(
+        "HTTP_CONNECT_TIMEOUT_SECONDS",
+        &mut http.connect_timeout_seconds,
+    )?;
+    override_field("HTTP_TIMEOUT_SECONDS", &mut http.timeout_seconds)?;
+    override_field("HTTP_PROXY", &mut http.proxy)?;
+    override_field("HTTP_CA_BUNDLE", &mut http.ca_bundle)?;
+
+    // Likewise the webhook receiver, which then needs at least a listen address and a secret
+    let webhook = [
+        "WEBHOOK_LISTEN",
+        "WEBHOOK_SECRET",
+        "WEBHOOK_PATH",
+        "WEBHOOK_FALLBACK_INTERVAL_SECONDS",
+    ];
+    if config.webhook.is_none() && any_set("", &webhook) {
+        config.webhook = Some(WebhookConfig {
+            listen: String::new(),
+            secret: String::new(),
+            path: default_webhook_path(),
+            fallback_interval_seconds: default_fallback_interval(),
+        });
+    }
+    if let Some(webhook) = &mut config.webhook {
+        override_field("WEBHOOK_LISTEN", &mut webhook.listen)?;
+        override_field("WEBHOOK_SECRET", &mut webhook.secret)?;
+        override_field("WEBHOOK_PATH", &mut webhook.path)?;
+        override_field(
+            "WEBHOOK_FALLBACK_INTERVAL_SECONDS",
+            &mut webhook.fallback_interval_seconds,
+        )?;
+    }
+
+    for (index, repo) in config.repos.iter_mut().enumerate() {
+        override_repo(repo, "")?;
+        override_repo(repo, &format!("REPOS_{}_", index))?;
+    }
+    Ok(())
+}
+
+// Resolve a secret reference: `env:NAME` reads an environment variable, `file:/path` reads a
+// file and `cmd:command` runs a shell command and takes its output, so the secret itself never
+// has to be written into config.toml. Anything else is used as it is.
+fn resolve_secret(value: &str) -> Result<String, String> {
+    if let Some(name) = value.strip_prefix("env:") {
+        return env::var(name).map_err(|_| format!("environment variable {} is not set", name));
+    }
+    if let Some(path) = value.strip_prefix("file:") {
+        return fs::read_to_string(path)
+            .map(|content| content.trim_end().to_string())
+            .map_err(|e| format!("cannot read {}: {}", path, e));
+    }
+    if let Some(command) = value.strip_prefix("cmd:") {
+        let output = if cfg!(windows) {
+            Command::new("cmd").arg("/C").arg(command).output()
+        } else {
+            Command::new("sh").arg("-c").arg(command).output()
+        }
+        .map_err(|e| format!("cannot run `{}`: {}", command, e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "`{}` exited with {} {}",
+                command,
+                output.status,
+                stderr.trim()
+            ));
+        }
+        return Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string());
+    }
+    Ok(value.to_string())
+}
+
+fn resolve_secrets(config: &mut Config) -> Result<(), String> {
+    if let Some(webhook) = &mut config.webhook {
+        webhook.secret =
+            resolve_secret(&webhook.secret).map_err(|e| format!("[webhook] secret: {}", e))?;
+    }
+    for repo in &mut config.repos {
+        if let Some(token) = &repo.github.access_token {
+            let name = repo.name();
+            repo.github.access_token =
+                Some(resolve_secret(token).map_err(|e| format!("{} access_token: {}", name, e))?);
+        }
+    }
+    Ok(())
+}
+
+fn resolve_path(base: &Path, path: &mut String) {
+    if Path::new(path.as_str()).is_relative() {
+        *path = base.join(path.as_str()).to_string_lossy().into_owned();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test uses its own variables, since tests run in parallel in one process.
+    fn set(name: &str, value: &str) {
+        env::set_var(format!("{}{}", ENV_PREFIX, name), value);
+    }
+
+    #[test]
+    fn reads_values_as_toml() {
+        set("TEST_NUMBER", "42");
+        set("TEST_BOOL", "true");
+        set("TEST_QUOTED", "\"quoted\"");
+        set("TEST_ENUM", "rebase");
+        let (mut number, mut flag, mut quoted) = (0u64, false, String::new());
+        let mut strategy = SyncStrategy::FfOnly;
+        override_field("TEST_NUMBER", &mut number).unwrap();
+        override_field("TEST_BOOL", &mut flag).unwrap();
+        override_field("TEST_QUOTED", &mut quoted).unwrap();
+        override_field("TEST_ENUM", &mut strategy).unwrap();
+        assert_eq!(number, 42);
+        assert!(flag);
+        assert_eq!(quoted, "quoted");
+        assert_eq!(strategy, SyncStrategy::Rebase);
+    }
+
+    #[test]
+    fn falls_back_to_a_plain_string() {
+        set("TEST_PATH", "/srv/app");
+        set("TEST_DIGITS", "0123");
+        set("TEST_OPTIONAL", "https://ghe.example.com/api/v3");
+        let (mut path, mut digits, mut url) = (String::new(), String::new(), None::<String>);
+        override_field("TEST_PATH", &mut path).unwrap();
+        override_field("TEST_DIGITS", &mut digits).unwrap();
+        override_field("TEST_OPTIONAL", &mut url).unwrap();
+        assert_eq!(path, "/srv/app");
+        assert_eq!(digits, "0123");
+        assert_eq!(url.as_deref(), Some("https://ghe.example.com/api/v3"));
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        set("TEST_BAD_NUMBER", "soon");
+        set("TEST_BAD_ENUM", "squash");
+        let mut number = 5u64;
+        let mut strategy = SyncStrategy::FfOnly;
+        let error = override_field("TEST_BAD_NUMBER", &mut number).unwrap_err();
+        assert!(error.starts_with("REPO_SYNC_TEST_BAD_NUMBER"));
+        assert!(override_field("TEST_BAD_ENUM", &mut strategy).is_err());
+        assert_eq!(number, 5);
+    }
+
+    #[test]
+    fn leaves_unset_fields_alone() {
+        let mut value = "from the file".to_string();
+        override_field("TEST_NEVER_SET", &mut value).unwrap();
+        assert_eq!(value, "from the file");
+    }
+
+    #[test]
+    fn adds_a_health_check_from_the_environment() {
+        let mut repo: RepoConfig = toml::from_str(
+            "[github]\nowner = \"o\"\nrepo = \"r\"\ntarget_branch = \"main\"\n\
+             [local_repo]\npath = \"/srv/app\"\ncheck_interval_seconds = 60\n",
+        )
+        .unwrap();
+        override_repo(&mut repo, "TEST_NO_CHECK_").unwrap();
+        assert!(repo.health_check.is_none());
+
+        set(
+            "TEST_CHECK_HEALTH_CHECK_URL",
+            "http://localhost:8080/health",
+        );
+        override_repo(&mut repo, "TEST_CHECK_").unwrap();
+        let check = repo.health_check.unwrap();
+        assert_eq!(check.url.as_deref(), Some("http://localhost:8080/health"));
+        assert_eq!(check.command, None);
+        assert_eq!(check.timeout_seconds, default_health_check_timeout());
+    }
+
+    #[test]
+    fn resolves_secret_references() {
+        set("TEST_SECRET", "hunter2");
+        assert_eq!(
+            resolve_secret("env:REPO_SYNC_TEST_SECRET").unwrap(),
+            "hunter2"
+        );
+        assert!(resolve_secret("env:REPO_SYNC_TEST_NO_SUCH_SECRET").is_err());
+        assert_eq!(resolve_secret("plain-token").unwrap(), "plain-token");
+    }
+}
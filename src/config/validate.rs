@@ -0,0 +1,625 @@
+use super::{Config, HookConfig, ProviderKind, RepoConfig};
+use crate::state;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+// Longest interval or timeout that is more likely a typo than intended: one week.
+const MAX_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+// Where a value lives in config.toml: the table header it sits under, which `[[repos]]` entry
+// and which hook in a list, if any, and its key.
+struct Key {
+    table: String,
+    repo: Option<usize>,
+    item: Option<usize>,
+    name: String,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = self.table.split('.').filter(|part| !part.is_empty());
+        if let Some(first) = parts.next() {
+            write!(f, "{}", first)?;
+            if let (true, Some(repo)) = (first == "repos", self.repo) {
+                write!(f, "[{}]", repo)?;
+            }
+            for part in parts {
+                write!(f, ".{}", part)?;
+            }
+            if let Some(item) = self.item {
+                write!(f, "[{}]", item)?;
+            }
+            write!(f, ".")?;
+        }
+        write!(f, "{}", self.name)
+    }
+}
+
+// Strip the spaces and quotes TOML allows in a table header, e.g. `[ repos."local_repo" ]`.
+fn normalize_header(header: &str) -> String {
+    header
+        .split('.')
+        .map(|part| part.trim().trim_matches('"'))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// The line the key is set on. Understands table headers and `key = value` lines; keys set
+// any other way, or through the environment, get no line.
+fn line_of(source: &str, key: &Key) -> Option<usize> {
+    let mut table = String::new();
+    let mut repo: Option<usize> = None;
+    let mut item = None;
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix("[[").and_then(|l| l.split("]]").next()) {
+            table = normalize_header(header);
+            if table == "repos" {
+                repo = Some(repo.map_or(0, |r| r + 1));
+                counts.clear();
+                item = None;
+            } else {
+                let count = counts.entry(table.clone()).or_insert(0);
+                item = Some(*count);
+                *count += 1;
+            }
+        } else if let Some(header) = line.strip_prefix('[').and_then(|l| l.split(']').next()) {
+            table = normalize_header(header);
+            item = None;
+        } else if let Some((name, _)) = line.split_once('=') {
+            if line.starts_with('#') || name.trim().trim_matches('"') != key.name {
+                continue;
+            }
+            let in_repo = key.repo.is_none() || repo == key.repo;
+            if table == key.table && in_repo && key.item.is_none_or(|i| item == Some(i)) {
+                return Some(number + 1);
+            }
+        }
+    }
+    None
+}
+
+// The line of the key named in an "unknown field" error from the TOML parser, which itself
+// reports the end of the enclosing table instead.
+pub fn unknown_key_line(source: &str, message: &str) -> Option<usize> {
+    let name = message.strip_prefix("unknown field `")?.split('`').next()?;
+    let table = message
+        .split("for key `")
+        .nth(1)
+        .and_then(|rest| rest.split('`').next())
+        .unwrap_or("");
+    let key = Key {
+        table: table.to_string(),
+        repo: None,
+        item: None,
+        name: name.to_string(),
+    };
+    line_of(source, &key)
+}
+
+// Collects every problem with a loaded config, each pointing at the key and line responsible.
+struct Validator<'a> {
+    source: &'a str,
+    // The first repo came from the old top-level [github] and [local_repo] tables.
+    legacy: bool,
+    errors: Vec<String>,
+}
+
+impl<'a> Validator<'a> {
+    fn top(&self, table: &str, name: &str) -> Key {
+        Key {
+            table: table.to_string(),
+            repo: None,
+            item: None,
+            name: name.to_string(),
+        }
+    }
+
+    // A key of the `index`-th configured repo, which may be the legacy top-level one.
+    fn repo(&self, index: usize, table: &str, name: &str) -> Key {
+        match (self.legacy, index) {
+            (true, 0) => self.top(table, name),
+            _ => Key {
+                table: format!("repos.{}", table),
+                repo: Some(index - self.legacy as usize),
+                item: None,
+                name: name.to_string(),
+            },
+        }
+    }
+
+    fn hook(&self, index: usize, list: &str, item: usize, name: &str) -> Key {
+        Key {
+            item: Some(item),
+            ..self.repo(index, list, name)
+        }
+    }
+
+    fn error(&mut self, key: Key, message: impl fmt::Display) {
+        let error = match line_of(self.source, &key) {
+            Some(line) => format!("line {}: {}: {}", line, key, message),
+            None => format!("{}: {}", key, message),
+        };
+        self.errors.push(error);
+    }
+
+    // Reject values such as `<git-username>` left over from config_example.toml. Only checked
+    // on the keys that have a placeholder there, since `<word>` is valid in a shell command.
+    fn placeholder(&mut self, key: Key, value: &str) {
+        let placeholder = value.split('<').skip(1).find_map(|rest| {
+            let (inside, _) = rest.split_once('>')?;
+            let is_word = !inside.is_empty()
+                && inside
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            is_word.then_some(inside)
+        });
+        if let Some(placeholder) = placeholder {
+            self.error(
+                key,
+                format!(
+                    "\"<{}>\" is a placeholder from the example config; replace it with a real value",
+                    placeholder
+                ),
+            );
+        }
+    }
+
+    fn seconds(&mut self, key: Key, value: u64, min: u64) {
+        if value < min || value > MAX_SECONDS {
+            self.error(
+                key,
+                format!(
+                    "must be between {} and {} seconds, got {}",
+                    min, MAX_SECONDS, value
+                ),
+            );
+        }
+    }
+
+    fn file_exists(&mut self, key: Key, path: &str) {
+        if !Path::new(path).is_file() {
+            self.error(key, format!("{} does not exist or is not a file", path));
+        }
+    }
+
+    // The file itself may not exist yet, but the directory it goes in must.
+    fn parent_exists(&mut self, key: Key, path: &str) {
+        match Path::new(path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => self.error(
+                key,
+                format!("the directory {} does not exist", dir.display()),
+            ),
+            _ => {}
+        }
+    }
+
+    fn hooks(&mut self, index: usize, list: &str, hooks: &[HookConfig]) {
+        for (item, hook) in hooks.iter().enumerate() {
+            if hook.command.trim().is_empty() {
+                self.error(self.hook(index, list, item, "command"), "must not be empty");
+            }
+            self.seconds(
+                self.hook(index, list, item, "timeout_seconds"),
+                hook.timeout_seconds,
+                1,
+            );
+        }
+    }
+
+    fn repo_config(&mut self, index: usize, repo: &RepoConfig) {
+        let github = &repo.github;
+        for (name, value) in [
+            ("owner", Some(&github.owner)),
+            ("repo", Some(&github.repo)),
+            ("access_token", github.access_token.as_ref()),
+        ] {
+            if let Some(value) = value {
+                self.placeholder(self.repo(index, "github", name), value);
+            }
+        }
+        if github.target_branch.trim().is_empty() {
+            self.error(
+                self.repo(index, "github", "target_branch"),
+                "must not be empty",
+            );
+        }
+        if github.provider != ProviderKind::Git {
+            for (name, value) in [("owner", &github.owner), ("repo", &github.repo)] {
+                if value.trim().is_empty() {
+                    self.error(
+                        self.repo(index, "github", name),
+                        "is required unless provider = \"git\"",
+                    );
+                }
+            }
+            if let Err(e) = github.api_base_url() {
+                self.error(self.repo(index, "github", "api_base_url"), e);
+            }
+        }
+
+        let local_repo = &repo.local_repo;
+        if let Some(clone_url) = &local_repo.clone_url {
+            self.placeholder(self.repo(index, "local_repo", "clone_url"), clone_url);
+        }
+        self.seconds(
+            self.repo(index, "local_repo", "check_interval_seconds"),
+            local_repo.check_interval_seconds,
+            1,
+        );
+        let path = Path::new(&local_repo.path);
+        if local_repo.clone_if_missing {
+            self.parent_exists(self.repo(index, "local_repo", "path"), &local_repo.path);
+        } else if !path.is_dir() {
+            self.error(
+                self.repo(index, "local_repo", "path"),
+                format!(
+                    "{} does not exist; clone it first or set clone_if_missing = true",
+                    local_repo.path
+                ),
+            );
+        }
+        if local_repo.clone_if_missing && repo.clone_url().is_none() {
+            let reason = if github.provider == ProviderKind::Git {
+                "provider = \"git\""
+            } else {
+                "a custom api_base_url"
+            };
+            self.error(
+                self.repo(index, "local_repo", "clone_url"),
+                format!("is required for clone_if_missing with {}", reason),
+            );
+        }
+
+        self.hooks(index, "pre_sync", &repo.pre_sync);
+        self.hooks(index, "post_sync", &repo.post_sync);
+
+        if let Some(check) = &repo.health_check {
+            if check.command.is_some() == check.url.is_some() {
+                self.error(
+                    self.repo(index, "health_check", "command"),
+                    "set exactly one of command or url",
+                );
+            }
+            self.seconds(
+                self.repo(index, "health_check", "timeout_seconds"),
+                check.timeout_seconds,
+                1,
+            );
+            self.seconds(
+                self.repo(index, "health_check", "delay_seconds"),
+                check.delay_seconds,
+                0,
+            );
+        }
+    }
+}
+
+// Check a loaded config for mistakes serde cannot catch: out-of-range intervals, paths that do
+// not exist and placeholders left over from config_example.toml. Returns one message per
+// problem, each naming the offending key and, where it can be found, its line in `source`.
+pub fn validate(config: &Config, source: &str, legacy: bool) -> Result<(), Vec<String>> {
+    let mut validator = Validator {
+        source,
+        legacy,
+        errors: Vec::new(),
+    };
+
+    validator.parent_exists(validator.top("", "cache_file"), &config.cache_file);
+    validator.parent_exists(validator.top("", "history_file"), &config.history_file);
+
+    let http = &config.http;
+    validator.seconds(
+        validator.top("http", "connect_timeout_seconds"),
+        http.connect_timeout_seconds,
+        1,
+    );
+    validator.seconds(
+        validator.top("http", "timeout_seconds"),
+        http.timeout_seconds,
+        1,
+    );
+    if let Some(ca_bundle) = &http.ca_bundle {
+        validator.file_exists(validator.top("http", "ca_bundle"), ca_bundle);
+    }
+
+    if let Some(webhook) = &config.webhook {
+        if let Err(e) = webhook.listen.parse::<SocketAddr>() {
+            validator.error(
+                validator.top("webhook", "listen"),
+                format!(
+                    "\"{}\" is not an address like 0.0.0.0:8080: {}",
+                    webhook.listen, e
+                ),
+            );
+        }
+        validator.placeholder(validator.top("webhook", "secret"), &webhook.secret);
+        if webhook.secret.is_empty() {
+            validator.error(validator.top("webhook", "secret"), "must not be empty");
+        }
+        validator.seconds(
+            validator.top("webhook", "fallback_interval_seconds"),
+            webhook.fallback_interval_seconds,
+            1,
+        );
+    }
+
+    for (index, repo) in config.repos.iter().enumerate() {
+        validator.repo_config(index, repo);
+    }
+
+    // Two tasks syncing one checkout would undo each other's work and share a state file
+    let mut checkouts: Vec<(PathBuf, usize)> = Vec::new();
+    for (index, repo) in config.repos.iter().enumerate() {
+        let checkout = state::canonical_checkout(&repo.local_repo.path);
+        match checkouts.iter().find(|(other, _)| *other == checkout) {
+            Some((_, first)) => validator.error(
+                validator.repo(index, "local_repo", "path"),
+                format!(
+                    "{} is already used by {}",
+                    repo.local_repo.path,
+                    validator.repo(*first, "local_repo", "path")
+                ),
+            ),
+            None => checkouts.push((checkout, index)),
+        }
+    }
+
+    match validator.errors.is_empty() {
+        true => Ok(()),
+        false => Err(validator.errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"cache_file = "cache.json"
+
+[[repos]]
+[repos.github]
+owner = "first"
+repo = "one"
+target_branch = "main"
+
+[repos.local_repo]
+path = "/srv/one"
+check_interval_seconds = 10
+
+[[repos.post_sync]]
+command = "make"
+
+[[repos.post_sync]]
+# command = "commented out"
+command = "systemctl restart one"
+timeout_seconds = 0
+
+[[repos]]
+[ repos."github" ]
+owner = "second"
+repo = "two"
+target_branch = "main"
+
+[repos.local_repo]
+"path" = "/srv/two"
+check_interval_seconds = 20
+
+[[repos.post_sync]]
+command = "make"
+"#;
+
+    fn key(table: &str, repo: Option<usize>, item: Option<usize>, name: &str) -> Key {
+        Key {
+            table: table.to_string(),
+            repo,
+            item,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn finds_top_level_keys() {
+        assert_eq!(line_of(SOURCE, &key("", None, None, "cache_file")), Some(1));
+        assert_eq!(line_of(SOURCE, &key("", None, None, "state_dir")), None);
+    }
+
+    #[test]
+    fn tells_repos_apart() {
+        let interval = |repo| {
+            key(
+                "repos.local_repo",
+                Some(repo),
+                None,
+                "check_interval_seconds",
+            )
+        };
+        assert_eq!(line_of(SOURCE, &interval(0)), Some(11));
+        assert_eq!(line_of(SOURCE, &interval(1)), Some(29));
+        assert_eq!(line_of(SOURCE, &interval(2)), None);
+        assert_eq!(
+            line_of(SOURCE, &key("repos.github", Some(1), None, "owner")),
+            Some(23)
+        );
+    }
+
+    #[test]
+    fn handles_quoted_keys_and_headers() {
+        assert_eq!(
+            line_of(SOURCE, &key("repos.local_repo", Some(1), None, "path")),
+            Some(28)
+        );
+    }
+
+    #[test]
+    fn counts_hooks_per_repo_and_skips_comments() {
+        let command = |repo, item| key("repos.post_sync", Some(repo), Some(item), "command");
+        assert_eq!(line_of(SOURCE, &command(0, 0)), Some(14));
+        assert_eq!(line_of(SOURCE, &command(0, 1)), Some(18));
+        assert_eq!(line_of(SOURCE, &command(1, 0)), Some(32));
+        assert_eq!(line_of(SOURCE, &command(1, 1)), None);
+        assert_eq!(
+            line_of(
+                SOURCE,
+                &key("repos.post_sync", Some(0), Some(1), "timeout_seconds")
+            ),
+            Some(19)
+        );
+    }
+
+    #[test]
+    fn formats_keys_like_the_config() {
+        let hook = key("repos.post_sync", Some(1), Some(0), "command");
+        assert_eq!(hook.to_string(), "repos[1].post_sync[0].command");
+        let top = key("http", None, None, "proxy");
+        assert_eq!(top.to_string(), "http.proxy");
+        assert_eq!(key("", None, None, "state_dir").to_string(), "state_dir");
+    }
+
+    #[test]
+    fn locates_unknown_keys_from_parser_errors() {
+        let source = "state_dir = \"state\"\nbogus = 1\n\n[http]\nproxyy = \"x\"\n";
+        let message = "unknown field `bogus`, expected one of `repos`, `http` at line 6 column 1";
+        assert_eq!(unknown_key_line(source, message), Some(2));
+        let message = "unknown field `proxyy`, expected `proxy` for key `http` at line 6 column 1";
+        assert_eq!(unknown_key_line(source, message), Some(5));
+        assert_eq!(unknown_key_line(source, "invalid type: string"), None);
+    }
+
+    fn errors(source: &str, legacy: bool) -> Vec<String> {
+        let mut config: Config = toml::from_str(source).unwrap();
+        if let (Some(github), Some(local_repo)) = (config.github.take(), config.local_repo.take()) {
+            config.repos.insert(
+                0,
+                RepoConfig {
+                    github,
+                    local_repo,
+                    pre_sync: Vec::new(),
+                    post_sync: Vec::new(),
+                    health_check: None,
+                    proxy: None,
+                },
+            );
+        }
+        match validate(&config, source, legacy) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors,
+        }
+    }
+
+    fn existing_dir() -> String {
+        std::env::temp_dir().to_string_lossy().replace('\\', "/")
+    }
+
+    #[test]
+    fn accepts_a_valid_config() {
+        let source = format!(
+            "[[repos]]\n[repos.github]\nowner = \"o\"\nrepo = \"r\"\ntarget_branch = \"main\"\n\
+             [repos.local_repo]\npath = \"{}\"\ncheck_interval_seconds = 60\n",
+            existing_dir()
+        );
+        assert_eq!(errors(&source, false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_every_problem_with_its_line() {
+        let source = "\
+[webhook]
+listen = \"8080\"
+secret = \"<webhook-secret>\"
+
+[[repos]]
+[repos.github]
+owner = \"<git-username>\"
+repo = \"r\"
+target_branch = \"main\"
+[repos.local_repo]
+path = \"/no/such/checkout\"
+check_interval_seconds = 0
+";
+        let errors = errors(source, false);
+        assert_eq!(errors.len(), 5, "{:#?}", errors);
+        assert!(errors[0].starts_with("line 2: webhook.listen: \"8080\" is not an address"));
+        assert!(errors[1].starts_with("line 3: webhook.secret: \"<webhook-secret>\""));
+        assert!(errors[2].starts_with("line 7: repos[0].github.owner: \"<git-username>\""));
+        assert!(errors[3].starts_with("line 12: repos[0].local_repo.check_interval_seconds"));
+        assert!(errors[4].starts_with("line 11: repos[0].local_repo.path: /no/such/checkout"));
+    }
+
+    #[test]
+    fn allows_angle_brackets_in_commands() {
+        let source = format!(
+            "[[repos]]\n[repos.github]\nowner = \"o\"\nrepo = \"r\"\ntarget_branch = \"main\"\n\
+             [repos.local_repo]\npath = \"{}\"\ncheck_interval_seconds = 60\n\
+             [[repos.pre_sync]]\ncommand = \"grep -q '<title>' index.html\"\n\
+             [repos.health_check]\ncommand = \"curl -s localhost | grep -q '<body>'\"\n",
+            existing_dir()
+        );
+        assert_eq!(errors(&source, false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_two_repos_in_one_checkout() {
+        let repo = |path: &str| {
+            format!(
+                "[[repos]]\n[repos.github]\nowner = \"o\"\nrepo = \"r\"\ntarget_branch = \"main\"\n\
+                 [repos.local_repo]\npath = \"{}\"\ncheck_interval_seconds = 60\n",
+                path
+            )
+        };
+        let dir = existing_dir();
+        let source = format!("{}{}", repo(&dir), repo(&format!("{}/.", dir)));
+        let errors = errors(&source, false);
+        assert_eq!(errors.len(), 1, "{:#?}", errors);
+        assert_eq!(
+            errors[0],
+            format!(
+                "line 15: repos[1].local_repo.path: {}/. is already used by repos[0].local_repo.path",
+                dir
+            )
+        );
+    }
+
+    #[test]
+    fn needs_a_clone_url_with_a_custom_api() {
+        let source = format!(
+            "[[repos]]\n[repos.github]\napi_base_url = \"https://ghe.example.com/api/v3\"\n\
+             owner = \"o\"\nrepo = \"r\"\ntarget_branch = \"main\"\n\
+             [repos.local_repo]\npath = \"{}/missing-checkout\"\ncheck_interval_seconds = 60\n\
+             clone_if_missing = true\n",
+            existing_dir()
+        );
+        let missing = errors(&source, false);
+        assert_eq!(missing.len(), 1, "{:#?}", missing);
+        assert_eq!(
+            missing[0],
+            "repos[0].local_repo.clone_url: is required for clone_if_missing with a custom api_base_url"
+        );
+
+        let source = format!(
+            "{}clone_url = \"https://ghe.example.com/o/r.git\"\n",
+            source
+        );
+        assert_eq!(errors(&source, false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn maps_the_legacy_layout() {
+        let source = format!(
+            "[github]\nowner = \"o\"\nrepo = \"r\"\ntarget_branch = \"main\"\n\
+             [local_repo]\npath = \"{0}\"\ncheck_interval_seconds = 0\n\n\
+             [[repos]]\n[repos.github]\nowner = \"o\"\nrepo = \"r\"\ntarget_branch = \"main\"\n\
+             [repos.local_repo]\npath = \"{0}\"\ncheck_interval_seconds = 9999999\n",
+            existing_dir()
+        );
+        let errors = errors(&source, true);
+        assert_eq!(errors.len(), 3, "{:#?}", errors);
+        assert!(errors[0].starts_with("line 7: local_repo.check_interval_seconds"));
+        assert!(errors[1].starts_with("line 16: repos[0].local_repo.check_interval_seconds"));
+        assert!(errors[2].starts_with("line 15: repos[0].local_repo.path"));
+        assert!(errors[2].ends_with("is already used by local_repo.path"));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::config::{Config, RepoConfig};
+use crate::sync::{self, SyncContext};
+use crate::webhook::{self, Trigger, Triggers};
+use log::{error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+// How often the config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// A running repository task and the handles used to wake it up or stop it.
+struct RepoTask {
+    config: RepoConfig,
+    trigger: Arc<Notify>,
+    stop: Arc<Notify>,
+    handle: JoinHandle<()>,
+}
+
+impl RepoTask {
+    fn spawn(config: RepoConfig, context: &SyncContext) -> Self {
+        let trigger = Arc::new(Notify::new());
+        let stop = Arc::new(Notify::new());
+        let handle = tokio::spawn(sync::run_repo(
+            config.clone(),
+            context.clone(),
+            trigger.clone(),
+            stop.clone(),
+        ));
+        RepoTask {
+            config,
+            trigger,
+            stop,
+            handle,
+        }
+    }
+
+    // Ask the task to stop and wait for its current check to finish, so the checkout and its
+    // state file are never shared with the task replacing it.
+    async fn stop(self) {
+        self.stop.notify_one();
+        if let Err(e) = self.handle.await {
+            error!("Repository task stopped unexpectedly: {}", e);
+        }
+    }
+}
+
+// The repository tasks and webhook receiver of `run`, kept in step with the config file.
+pub struct Daemon {
+    config: Config,
+    context: SyncContext,
+    tasks: Vec<RepoTask>,
+    triggers: Triggers,
+    webhook: Option<JoinHandle<()>>,
+}
+
+impl Daemon {
+    // Spawn one polling task per configured repository, and the webhook receiver if enabled.
+    pub fn start(config: Config, context: SyncContext) -> Self {
+        let tasks = config
+            .repos
+            .iter()
+            .map(|repo| RepoTask::spawn(repo.clone(), &context))
+            .collect();
+        let mut daemon = Daemon {
+            config,
+            context,
+            tasks,
+            triggers: Arc::new(RwLock::new(Vec::new())),
+            webhook: None,
+        };
+        daemon.update_triggers();
+        daemon.webhook = daemon.spawn_webhook();
+        daemon
+    }
+
+    // Switch to `config`: repos that were added start, removed ones stop and changed ones
+    // restart, while unchanged repos carry on undisturbed. If the shared settings changed,
+    // every repo restarts with the new ones. On error the running config is kept as it is.
+    pub async fn reload(&mut self, config: Config) -> Result<(), String> {
+        if config == self.config {
+            info!("Configuration unchanged.");
+            return Ok(());
+        }
+
+        let shared_changed = config.http != self.config.http
+            || config.cache_file != self.config.cache_file
+            || config.history_file != self.config.history_file
+            || config.state_dir != self.config.state_dir
+            || config.webhook.as_ref().map(|w| w.fallback_interval_seconds)
+                != self
+                    .config
+                    .webhook
+                    .as_ref()
+                    .map(|w| w.fallback_interval_seconds);
+        if shared_changed {
+            self.context = SyncContext::new(&config)?;
+        }
+
+        // Repos are told apart by their checkout, since two tasks must never share one
+        let (mut kept, mut stopping) = (Vec::new(), Vec::new());
+        for task in self.tasks.drain(..) {
+            if !shared_changed && config.repos.contains(&task.config) {
+                kept.push(task);
+                continue;
+            }
+            match find(&config.repos, &task.config) {
+                Some(_) => info!(
+                    "[{}] Configuration changed, restarting.",
+                    task.config.name()
+                ),
+                None => info!("[{}] Removed from the configuration.", task.config.name()),
+            }
+            stopping.push(task);
+        }
+        for task in stopping {
+            task.stop().await;
+        }
+        for repo in &config.repos {
+            if !kept.iter().any(|task| task.config == *repo) {
+                if find(&self.config.repos, repo).is_none() {
+                    info!("[{}] Added to the configuration.", repo.name());
+                }
+                kept.push(RepoTask::spawn(repo.clone(), &self.context));
+            }
+        }
+        self.tasks = kept;
+
+        let webhook_changed = config.webhook != self.config.webhook;
+        self.config = config;
+        self.update_triggers();
+        if webhook_changed {
+            if let Some(handle) = self.webhook.take() {
+                // Wait for the listener to be closed before binding the new one
+                handle.abort();
+                let _ = handle.await;
+            }
+            self.webhook = self.spawn_webhook();
+        }
+        info!(
+            "Configuration reloaded: {} repository configuration(s).",
+            self.tasks.len()
+        );
+        Ok(())
+    }
+
+    fn update_triggers(&self) {
+        *self.triggers.write().unwrap() = self
+            .tasks
+            .iter()
+            .filter(|task| webhook::can_trigger(&task.config))
+            .map(|task| Trigger::new(&task.config, task.trigger.clone()))
+            .collect();
+    }
+
+    fn spawn_webhook(&self) -> Option<JoinHandle<()>> {
+        let webhook_config = self.config.webhook.clone()?;
+        Some(tokio::spawn(webhook::serve(
+            webhook_config,
+            self.triggers.clone(),
+        )))
+    }
+}
+
+// The repo in `repos` with the same checkout as `repo`, if any.
+fn find<'a>(repos: &'a [RepoConfig], repo: &RepoConfig) -> Option<&'a RepoConfig> {
+    repos
+        .iter()
+        .find(|other| other.local_repo.path == repo.local_repo.path)
+}
+
+// Notices when the config file should be read again: when it changes on disk or, on Unix,
+// when the process receives SIGHUP.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> Self {
+        ConfigWatcher {
+            path: path.to_path_buf(),
+            modified: modified_time(path),
+            #[cfg(unix)]
+            hangup: match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(signal) => Some(signal),
+                Err(e) => {
+                    warn!(
+                        "Cannot listen for SIGHUP, only watching the config file: {}",
+                        e
+                    );
+                    None
+                }
+            },
+        }
+    }
+
+    // Wait until the config should be reloaded.
+    pub async fn changed(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.hangup() => {
+                    info!("Received SIGHUP, reloading {}", self.path.display());
+                    self.modified = modified_time(&self.path);
+                    return;
+                }
+                _ = sleep(WATCH_INTERVAL) => {}
+            }
+            let modified = modified_time(&self.path);
+            if modified != self.modified {
+                self.modified = modified;
+                info!("{} changed, reloading it.", self.path.display());
+                return;
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    async fn hangup(&mut self) {
+        match &mut self.hangup {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn hangup(&mut self) {
+        std::future::pending().await
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
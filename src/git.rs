@@ -0,0 +1,1160 @@
+use crate::config::{BranchPolicy, DirtyPolicy, RepoConfig, SyncStrategy};
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+    AnnotatedCommit, BranchType, Commit, Cred, ErrorCode, FetchOptions, Index, ProxyOptions,
+    RebaseOptions, RemoteCallbacks, Repository, ResetType, Signature, StatusOptions,
+};
+use log::{info, warn};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// The step of a sync that failed, so the log says exactly what went wrong.
+#[derive(Debug)]
+pub enum SyncError {
+    Clone(git2::Error),
+    Fetch(git2::Error),
+    NotFastForward,
+    WrongBranch {
+        expected: String,
+        found: Option<String>,
+    },
+    DirtyWorkTree(Vec<String>),
+    Conflict(String),
+    Checkout(git2::Error),
+    Git(git2::Error),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Clone(e) => write!(f, "clone failed: {}", e),
+            SyncError::Fetch(e) => write!(f, "fetch failed: {}", e),
+            SyncError::NotFastForward => {
+                write!(f, "local branch has diverged and cannot be fast-forwarded")
+            }
+            SyncError::WrongBranch { expected, found } => match found {
+                Some(found) => write!(
+                    f,
+                    "checkout is on branch {} instead of {}, refusing to sync",
+                    found, expected
+                ),
+                None => write!(
+                    f,
+                    "checkout has a detached HEAD instead of branch {}, refusing to sync",
+                    expected
+                ),
+            },
+            SyncError::DirtyWorkTree(paths) => write!(
+                f,
+                "working tree has local changes in {}, refusing to sync",
+                paths.join(", ")
+            ),
+            SyncError::Conflict(msg) => write!(f, "conflict: {}", msg),
+            SyncError::Checkout(e) => write!(f, "checkout failed: {}", e),
+            SyncError::Git(e) => write!(f, "git error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl From<git2::Error> for SyncError {
+    fn from(e: git2::Error) -> Self {
+        SyncError::Git(e)
+    }
+}
+
+// What a successful sync did to the local checkout.
+pub enum SyncOutcome {
+    UpToDate,
+    FastForwarded {
+        from: String,
+        to: String,
+    },
+    Rebased {
+        from: String,
+        to: String,
+        commits: usize,
+    },
+    Merged {
+        from: String,
+        to: String,
+        merged: String,
+    },
+    Reset {
+        from: String,
+        to: String,
+        discarded: usize,
+    },
+    // Moved back from a synced commit to the one before it, see `roll_back`.
+    RolledBack {
+        from: String,
+        to: String,
+    },
+}
+
+impl SyncOutcome {
+    // The commits HEAD moved between, or None if nothing changed.
+    pub fn moved(&self) -> Option<(&str, &str)> {
+        match self {
+            SyncOutcome::UpToDate => None,
+            SyncOutcome::FastForwarded { from, to }
+            | SyncOutcome::Rebased { from, to, .. }
+            | SyncOutcome::Merged { from, to, .. }
+            | SyncOutcome::Reset { from, to, .. }
+            | SyncOutcome::RolledBack { from, to } => Some((from, to)),
+        }
+    }
+}
+
+impl fmt::Display for SyncOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncOutcome::UpToDate => write!(f, "already up to date"),
+            SyncOutcome::FastForwarded { from, to } => {
+                write!(f, "fast-forwarded {} -> {}", short(from), short(to))
+            }
+            SyncOutcome::Rebased { from, to, commits } => write!(
+                f,
+                "rebased {} local commit(s) from {} onto the remote, now at {}",
+                commits,
+                short(from),
+                short(to)
+            ),
+            SyncOutcome::Merged { from, to, merged } => write!(
+                f,
+                "merged remote {} into {}, created merge commit {}",
+                short(merged),
+                short(from),
+                short(to)
+            ),
+            SyncOutcome::Reset {
+                from,
+                to,
+                discarded,
+            } => write!(
+                f,
+                "hard reset {} -> {}, discarding {} local commit(s)",
+                short(from),
+                short(to),
+                discarded
+            ),
+            SyncOutcome::RolledBack { from, to } => {
+                write!(f, "rolled back {} -> {}", short(from), short(to))
+            }
+        }
+    }
+}
+
+// How the local branch relates to the remote-tracking branch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BranchState {
+    UpToDate,
+    Behind(usize),
+    Ahead(usize),
+    Diverged { ahead: usize, behind: usize },
+}
+
+impl fmt::Display for BranchState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BranchState::UpToDate => write!(f, "up to date with the remote"),
+            BranchState::Behind(n) => write!(f, "{} commit(s) behind the remote", n),
+            BranchState::Ahead(n) => write!(f, "{} commit(s) ahead of the remote", n),
+            BranchState::Diverged { ahead, behind } => write!(
+                f,
+                "diverged from the remote ({} ahead, {} behind)",
+                ahead, behind
+            ),
+        }
+    }
+}
+
+fn short(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+// Credentials for fetching: the configured access token over HTTPS, otherwise the SSH agent
+// or whatever default credentials libgit2 can find.
+pub fn remote_callbacks(config: &RepoConfig) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |_url, username, allowed| {
+        if allowed.is_user_pass_plaintext() {
+            if let Some(token) = &config.github.access_token {
+                return Cred::userpass_plaintext(config.github.provider.token_username(), token);
+            }
+        }
+        if allowed.is_ssh_key() {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        Cred::default()
+    });
+    callbacks
+}
+
+// Proxy for git network operations: the configured proxy, otherwise whatever git's own
+// http.proxy setting or the HTTPS_PROXY environment variable says.
+pub fn proxy_options(config: &RepoConfig) -> ProxyOptions<'_> {
+    let mut options = ProxyOptions::new();
+    match &config.proxy {
+        Some(url) => options.url(url),
+        None => options.auto(),
+    };
+    options
+}
+
+// Whether `path` is missing or an empty directory, i.e. somewhere a fresh clone can go.
+pub fn is_missing_checkout(path: &str) -> bool {
+    match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => !Path::new(path).exists(),
+    }
+}
+
+// Clone the configured repository at the target branch into the local path.
+pub fn clone_repo(config: &RepoConfig) -> Result<Repository, SyncError> {
+    let url = config.clone_url().ok_or_else(|| {
+        SyncError::Clone(git2::Error::from_str(
+            "clone_url must be set to clone with the git provider or a custom api_base_url",
+        ))
+    })?;
+    let remote_name = config.local_repo.remote.clone();
+    info!(
+        "Cloning {} ({}) into {}...",
+        url, config.github.target_branch, config.local_repo.path
+    );
+
+    let mut options = FetchOptions::new();
+    options
+        .remote_callbacks(remote_callbacks(config))
+        .proxy_options(proxy_options(config));
+    RepoBuilder::new()
+        .branch(&config.github.target_branch)
+        .fetch_options(options)
+        .remote_create(move |repo, _name, url| repo.remote(&remote_name, url))
+        .clone(&url, Path::new(&config.local_repo.path))
+        .map_err(SyncError::Clone)
+}
+
+// The remote-tracking ref the target branch is fetched into.
+fn tracking_ref(config: &RepoConfig) -> String {
+    format!(
+        "refs/remotes/{}/{}",
+        config.local_repo.remote, config.github.target_branch
+    )
+}
+
+// Fetch the target branch into its remote-tracking ref.
+pub fn fetch_target_branch(repo: &Repository, config: &RepoConfig) -> Result<(), SyncError> {
+    let remote_name = &config.local_repo.remote;
+    let branch = &config.github.target_branch;
+    let refspec = format!("+refs/heads/{}:{}", branch, tracking_ref(config));
+
+    let mut remote = repo.find_remote(remote_name).map_err(SyncError::Fetch)?;
+    let mut options = FetchOptions::new();
+    options
+        .remote_callbacks(remote_callbacks(config))
+        .proxy_options(proxy_options(config));
+
+    info!("Fetching {} from {}...", branch, remote_name);
+    remote
+        .fetch(&[&refspec], Some(&mut options), None)
+        .map_err(SyncError::Fetch)?;
+
+    Ok(())
+}
+
+// Name of the checked out branch, or None for a detached HEAD.
+fn current_branch(repo: &Repository) -> Result<Option<String>, SyncError> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Ok(None);
+    }
+    Ok(head.shorthand().map(str::to_string))
+}
+
+// Make sure HEAD is on the target branch before anything is compared or pulled. Depending on
+// the branch policy this either refuses or switches branches, creating the local branch from
+// the remote when it does not exist yet.
+pub fn ensure_target_branch(repo: &Repository, config: &RepoConfig) -> Result<(), SyncError> {
+    let expected = &config.github.target_branch;
+    let found = current_branch(repo)?;
+    if found.as_deref() == Some(expected.as_str()) {
+        return Ok(());
+    }
+
+    if config.local_repo.branch_policy == BranchPolicy::Warn {
+        return Err(SyncError::WrongBranch {
+            expected: expected.clone(),
+            found,
+        });
+    }
+
+    info!(
+        "Checking out branch {} (was {})",
+        expected,
+        found.as_deref().unwrap_or("detached HEAD")
+    );
+    let branch = match repo.find_branch(expected, BranchType::Local) {
+        Ok(branch) => branch,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            fetch_target_branch(repo, config)?;
+            let start = repo
+                .find_reference(&tracking_ref(config))?
+                .peel_to_commit()?;
+            info!(
+                "Creating local branch {} at {}",
+                expected,
+                short(&start.id().to_string())
+            );
+            let mut branch = repo.branch(expected, &start, false)?;
+            branch.set_upstream(Some(&format!("{}/{}", config.local_repo.remote, expected)))?;
+            branch
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let reference = branch.into_reference();
+    let commit = reference.peel_to_commit()?;
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))
+        .map_err(SyncError::Checkout)?;
+    let name = reference
+        .name()
+        .ok_or_else(|| SyncError::Git(git2::Error::from_str("branch name is not valid UTF-8")))?;
+    repo.set_head(name)?;
+    Ok(())
+}
+
+// Fetch only when the remote-tracking ref does not already point at `remote_sha`, so a branch
+// that stays ahead of the remote is not re-fetched on every check.
+pub fn fetch_if_stale(
+    repo: &Repository,
+    config: &RepoConfig,
+    remote_sha: &str,
+) -> Result<(), SyncError> {
+    let current = repo
+        .find_reference(&tracking_ref(config))
+        .and_then(|r| r.peel_to_commit())
+        .map(|c| c.id().to_string());
+    match current {
+        Ok(sha) if sha == remote_sha => Ok(()),
+        _ => fetch_target_branch(repo, config),
+    }
+}
+
+// The commit the remote-tracking ref points at, i.e. what a pull would apply.
+pub fn fetched_sha(repo: &Repository, config: &RepoConfig) -> Result<String, SyncError> {
+    let commit = repo
+        .find_reference(&tracking_ref(config))?
+        .peel_to_commit()?;
+    Ok(commit.id().to_string())
+}
+
+// Classify the local branch against the remote-tracking branch.
+pub fn branch_state(repo: &Repository, config: &RepoConfig) -> Result<BranchState, SyncError> {
+    let local = repo.head()?.peel_to_commit()?.id();
+    let remote = repo
+        .find_reference(&tracking_ref(config))?
+        .peel_to_commit()?
+        .id();
+
+    let state = match repo.graph_ahead_behind(local, remote)? {
+        (0, 0) => BranchState::UpToDate,
+        (0, behind) => BranchState::Behind(behind),
+        (ahead, 0) => BranchState::Ahead(ahead),
+        (ahead, behind) => BranchState::Diverged { ahead, behind },
+    };
+    Ok(state)
+}
+
+// Apply the remote changes to the local branch with the configured strategy. `state` is the
+// result of `branch_state` after the latest fetch.
+pub fn pull_latest_changes(
+    repo: &mut Repository,
+    config: &RepoConfig,
+    state: BranchState,
+) -> Result<SyncOutcome, SyncError> {
+    let strategy = config.local_repo.strategy;
+    // Reset-hard mirrors the remote exactly, so it also undoes local-only commits.
+    let needs_update = match state {
+        BranchState::UpToDate => false,
+        BranchState::Ahead(_) => strategy == SyncStrategy::ResetHard,
+        BranchState::Behind(_) | BranchState::Diverged { .. } => true,
+    };
+    if !needs_update {
+        return Ok(SyncOutcome::UpToDate);
+    }
+    if strategy == SyncStrategy::FfOnly && matches!(state, BranchState::Diverged { .. }) {
+        return Err(SyncError::NotFastForward);
+    }
+
+    info!("Pulling latest changes using the {} strategy...", strategy);
+    let stashed = prepare_work_tree(repo, config)?;
+    let result = apply_remote_changes(repo, config, state);
+    if stashed {
+        restore_stash(repo);
+    }
+    result
+}
+
+fn apply_remote_changes(
+    repo: &Repository,
+    config: &RepoConfig,
+    state: BranchState,
+) -> Result<SyncOutcome, SyncError> {
+    let fetched = repo.find_reference(&tracking_ref(config))?;
+    let upstream = repo.reference_to_annotated_commit(&fetched)?;
+    let target = fetched.peel_to_commit()?;
+    let local = repo.head()?.peel_to_commit()?;
+
+    match (config.local_repo.strategy, state) {
+        (SyncStrategy::ResetHard, _) => reset_hard(repo, &local, &target),
+        (_, BranchState::Behind(_)) => fast_forward(repo, &local, &target),
+        (SyncStrategy::Rebase, _) => rebase(repo, &local, &upstream),
+        (SyncStrategy::Merge, _) => merge(repo, &local, &target),
+        (SyncStrategy::FfOnly, _) => Err(SyncError::NotFastForward),
+    }
+}
+
+// Put the checkout back on `to`, the commit it was on before a sync, undoing whatever the sync
+// brought in. Local edits go through the dirty tree policy exactly as they do for a sync, and
+// the working tree is updated with a safe checkout, so they are never silently lost.
+pub fn roll_back(
+    repo: &mut Repository,
+    config: &RepoConfig,
+    to: &str,
+) -> Result<SyncOutcome, SyncError> {
+    let from = repo.head()?.peel_to_commit()?.id();
+    let to = git2::Oid::from_str(to)?;
+    let stashed = prepare_work_tree(repo, config)?;
+    let result = repo
+        .find_commit(to)
+        .map_err(SyncError::from)
+        .and_then(|target| move_head(repo, &target, "repo-sync: roll back"));
+    if stashed {
+        restore_stash(repo);
+    }
+    result?;
+    Ok(SyncOutcome::RolledBack {
+        from: from.to_string(),
+        to: to.to_string(),
+    })
+}
+
+// Authors of the commits reachable from `to` but not from `from`, one entry per commit, newest
+// first.
+pub fn commit_authors(repo: &Repository, from: &str, to: &str) -> Result<Vec<String>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.push(git2::Oid::from_str(to)?)?;
+    walk.hide(git2::Oid::from_str(from)?)?;
+    walk.map(|oid| {
+        let commit = repo.find_commit(oid?)?;
+        let author = commit.author().name().unwrap_or("unknown").to_string();
+        Ok(author)
+    })
+    .collect()
+}
+
+// Paths that differ between the trees of two commits.
+pub fn changed_files(repo: &Repository, from: &str, to: &str) -> Result<Vec<String>, git2::Error> {
+    let old_tree = repo.find_commit(git2::Oid::from_str(from)?)?.tree()?;
+    let new_tree = repo.find_commit(git2::Oid::from_str(to)?)?.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+// Paths of tracked files with staged or unstaged changes. Untracked files are left alone; a
+// checkout that would overwrite one still fails safely.
+pub fn dirty_paths(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    let statuses = repo.statuses(Some(&mut options))?;
+    Ok(statuses
+        .iter()
+        .filter_map(|entry| entry.path().map(str::to_string))
+        .collect())
+}
+
+// Apply the dirty tree policy before touching the working tree. Returns whether local changes
+// were stashed and need to be re-applied afterwards.
+fn prepare_work_tree(repo: &mut Repository, config: &RepoConfig) -> Result<bool, SyncError> {
+    let paths = dirty_paths(repo)?;
+    if paths.is_empty() {
+        return Ok(false);
+    }
+
+    match config.local_repo.dirty_policy {
+        DirtyPolicy::Refuse => Err(SyncError::DirtyWorkTree(paths)),
+        DirtyPolicy::Stash => {
+            info!("Stashing local changes in {}", paths.join(", "));
+            let signature = signature(repo)?;
+            repo.stash_save(&signature, "repo-sync: before sync", None)?;
+            Ok(true)
+        }
+        DirtyPolicy::Discard => {
+            warn!("Discarding local changes in {}", paths.join(", "));
+            let head = repo.head()?.peel_to_commit()?;
+            repo.reset(head.as_object(), ResetType::Hard, None)
+                .map_err(SyncError::Checkout)?;
+            Ok(false)
+        }
+    }
+}
+
+// Re-apply changes stashed by `prepare_work_tree`. The stash is only dropped once it applies
+// without conflicts, so nothing is lost if the synced changes touched the same lines.
+fn restore_stash(repo: &mut Repository) {
+    info!("Re-applying stashed local changes...");
+    if let Err(e) = repo.stash_apply(0, None) {
+        warn!(
+            "Failed to re-apply stashed local changes, they were kept in stash@{{0}}: {}",
+            e
+        );
+        return;
+    }
+
+    let conflicts = match conflicted_paths(repo) {
+        Ok(paths) => paths,
+        Err(e) => {
+            warn!(
+                "Failed to check re-applied changes for conflicts, keeping stash@{{0}}: {}",
+                e
+            );
+            return;
+        }
+    };
+    if !conflicts.is_empty() {
+        warn!(
+            "Stashed local changes conflict with the synced changes in {}; conflict markers were written and the changes were kept in stash@{{0}}",
+            conflicts.join(", ")
+        );
+        return;
+    }
+
+    match repo.stash_drop(0) {
+        Ok(()) => info!("Stashed local changes re-applied."),
+        Err(e) => warn!(
+            "Stashed local changes re-applied but stash@{{0}} could not be dropped: {}",
+            e
+        ),
+    }
+}
+
+// Paths with unresolved conflicts in the index.
+fn conflicted_paths(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    index_conflicts(&repo.index()?)
+}
+
+fn index_conflicts(index: &Index) -> Result<Vec<String>, git2::Error> {
+    Ok(index
+        .conflicts()?
+        .filter_map(|c| c.ok())
+        .filter_map(|c| c.our.or(c.their))
+        .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+        .collect())
+}
+
+// Write `tree_commit` to the working tree and move HEAD to it. The working tree is updated first
+// so the branch is never moved onto a tree we failed to write.
+fn move_head(repo: &Repository, tree_commit: &Commit<'_>, reflog: &str) -> Result<(), SyncError> {
+    repo.checkout_tree(tree_commit.as_object(), Some(CheckoutBuilder::new().safe()))
+        .map_err(SyncError::Checkout)?;
+    repo.head()?.set_target(tree_commit.id(), reflog)?;
+    Ok(())
+}
+
+fn fast_forward(
+    repo: &Repository,
+    local: &Commit<'_>,
+    target: &Commit<'_>,
+) -> Result<SyncOutcome, SyncError> {
+    move_head(repo, target, "repo-sync: fast-forward")?;
+    Ok(SyncOutcome::FastForwarded {
+        from: local.id().to_string(),
+        to: target.id().to_string(),
+    })
+}
+
+fn reset_hard(
+    repo: &Repository,
+    local: &Commit<'_>,
+    target: &Commit<'_>,
+) -> Result<SyncOutcome, SyncError> {
+    let (discarded, _) = repo.graph_ahead_behind(local.id(), target.id())?;
+    repo.reset(target.as_object(), ResetType::Hard, None)
+        .map_err(SyncError::Checkout)?;
+    Ok(SyncOutcome::Reset {
+        from: local.id().to_string(),
+        to: target.id().to_string(),
+        discarded,
+    })
+}
+
+// Replay local commits on top of the remote branch. The rebase runs in memory and the working
+// tree is only touched once every commit has applied cleanly.
+fn rebase(
+    repo: &Repository,
+    local: &Commit<'_>,
+    upstream: &AnnotatedCommit<'_>,
+) -> Result<SyncOutcome, SyncError> {
+    let branch = repo.find_annotated_commit(local.id())?;
+    let signature = signature(repo)?;
+    let mut options = RebaseOptions::new();
+    options.inmemory(true);
+
+    let mut rebase = repo.rebase(Some(&branch), Some(upstream), None, Some(&mut options))?;
+    let mut head = upstream.id();
+    let mut commits = 0;
+
+    while let Some(operation) = rebase.next() {
+        let operation = operation?;
+        if rebase.inmemory_index()?.has_conflicts() {
+            rebase.abort()?;
+            return Err(SyncError::Conflict(format!(
+                "local commit {} does not apply cleanly on the remote branch",
+                short(&operation.id().to_string())
+            )));
+        }
+        match rebase.commit(None, &signature, None) {
+            Ok(id) => {
+                head = id;
+                commits += 1;
+            }
+            // The change is already upstream, so there is nothing to replay.
+            Err(e) if e.code() == ErrorCode::Applied => {}
+            Err(e) => {
+                rebase.abort()?;
+                return Err(e.into());
+            }
+        }
+    }
+    rebase.finish(Some(&signature))?;
+
+    let rebased = repo.find_commit(head)?;
+    move_head(repo, &rebased, "repo-sync: rebase")?;
+    Ok(SyncOutcome::Rebased {
+        from: local.id().to_string(),
+        to: head.to_string(),
+        commits,
+    })
+}
+
+// Create a merge commit joining the local branch and the remote branch.
+fn merge(
+    repo: &Repository,
+    local: &Commit<'_>,
+    target: &Commit<'_>,
+) -> Result<SyncOutcome, SyncError> {
+    let mut index = repo.merge_commits(local, target, None)?;
+    if index.has_conflicts() {
+        let paths = index_conflicts(&index)?;
+        return Err(SyncError::Conflict(format!(
+            "merge conflicts in {}",
+            paths.join(", ")
+        )));
+    }
+
+    let signature = signature(repo)?;
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    let message = format!("Merge remote changes {}", short(&target.id().to_string()));
+    let id = repo.commit(
+        None,
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &[local, target],
+    )?;
+
+    let merge_commit = repo.find_commit(id)?;
+    move_head(repo, &merge_commit, "repo-sync: merge")?;
+    Ok(SyncOutcome::Merged {
+        from: local.id().to_string(),
+        to: id.to_string(),
+        merged: target.id().to_string(),
+    })
+}
+
+// Identity for commits created by a rebase or merge, falling back to a fixed one when the
+// repository has no user configured.
+fn signature(repo: &Repository) -> Result<Signature<'static>, SyncError> {
+    match repo.signature() {
+        Ok(signature) => Ok(signature),
+        Err(_) => Ok(Signature::now("repo-sync", "repo-sync@localhost")?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GitHubConfig, LocalRepoConfig, ProviderKind};
+    use git2::{Oid, RepositoryInitOptions};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // A scratch directory holding an upstream repository and a clone of it, removed when the
+    // test ends. The clone fetches from the upstream over the local file transport.
+    struct Sandbox {
+        dir: PathBuf,
+        upstream: Repository,
+        local: Repository,
+        config: RepoConfig,
+    }
+
+    impl Drop for Sandbox {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    impl Sandbox {
+        // An upstream with one commit of `a.txt` on main, and a fresh clone of it.
+        fn new(strategy: SyncStrategy, dirty_policy: DirtyPolicy) -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "repo-sync-test-{}-{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::SeqCst)
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            let mut options = RepositoryInitOptions::new();
+            options.initial_head("main");
+            let upstream = Repository::init_opts(dir.join("upstream"), &options).unwrap();
+            commit(&upstream, "a.txt", "one\n", "Add a");
+
+            let config = RepoConfig {
+                github: GitHubConfig {
+                    provider: ProviderKind::Git,
+                    api_base_url: None,
+                    owner: String::new(),
+                    repo: String::new(),
+                    target_branch: "main".to_string(),
+                    access_token: None,
+                },
+                local_repo: LocalRepoConfig {
+                    path: dir.join("local").to_string_lossy().into_owned(),
+                    check_interval_seconds: 60,
+                    remote: "origin".to_string(),
+                    strategy,
+                    dirty_policy,
+                    branch_policy: BranchPolicy::Warn,
+                    clone_if_missing: true,
+                    clone_url: Some(dir.join("upstream").to_string_lossy().into_owned()),
+                },
+                pre_sync: Vec::new(),
+                post_sync: Vec::new(),
+                health_check: None,
+                proxy: None,
+            };
+            let local = clone_repo(&config).unwrap();
+            Sandbox {
+                dir,
+                upstream,
+                local,
+                config,
+            }
+        }
+
+        // Fetch and apply the remote changes, the way a sync does.
+        fn pull(&mut self) -> Result<SyncOutcome, SyncError> {
+            fetch_target_branch(&self.local, &self.config)?;
+            let state = branch_state(&self.local, &self.config)?;
+            pull_latest_changes(&mut self.local, &self.config, state)
+        }
+
+        fn read(&self, file: &str) -> String {
+            fs::read_to_string(self.local.workdir().unwrap().join(file)).unwrap()
+        }
+
+        fn write(&self, file: &str, content: &str) {
+            fs::write(self.local.workdir().unwrap().join(file), content).unwrap();
+        }
+
+        fn stash_count(&mut self) -> usize {
+            let mut count = 0;
+            self.local
+                .stash_foreach(|_, _, _| {
+                    count += 1;
+                    true
+                })
+                .unwrap();
+            count
+        }
+    }
+
+    // Commit `file` with `content` on top of HEAD, through the index like `git commit -a`.
+    fn commit(repo: &Repository, file: &str, content: &str, message: &str) -> Oid {
+        fs::write(repo.workdir().unwrap().join(file), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(file)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&Commit<'_>> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    fn head(repo: &Repository) -> Oid {
+        repo.head().unwrap().peel_to_commit().unwrap().id()
+    }
+
+    #[test]
+    fn fast_forwards_to_the_remote() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let from = head(&sandbox.local);
+        let to = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        match sandbox.pull().unwrap() {
+            SyncOutcome::FastForwarded { from: old, to: new } => {
+                assert_eq!(old, from.to_string());
+                assert_eq!(new, to.to_string());
+            }
+            outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        assert_eq!(head(&sandbox.local), to);
+        assert_eq!(sandbox.read("a.txt"), "two\n");
+        assert_eq!(
+            current_branch(&sandbox.local).unwrap().as_deref(),
+            Some("main")
+        );
+        assert!(dirty_paths(&sandbox.local).unwrap().is_empty());
+    }
+
+    #[test]
+    fn leaves_an_up_to_date_branch_alone() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let before = head(&sandbox.local);
+        assert!(matches!(sandbox.pull().unwrap(), SyncOutcome::UpToDate));
+        assert_eq!(head(&sandbox.local), before);
+    }
+
+    #[test]
+    fn ff_only_refuses_a_diverged_branch() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let local = commit(&sandbox.local, "b.txt", "local\n", "Add b");
+        commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        assert!(matches!(sandbox.pull(), Err(SyncError::NotFastForward)));
+        assert_eq!(head(&sandbox.local), local);
+        assert_eq!(sandbox.read("a.txt"), "one\n");
+    }
+
+    #[test]
+    fn rebase_replays_local_commits_on_the_remote() {
+        let mut sandbox = Sandbox::new(SyncStrategy::Rebase, DirtyPolicy::Refuse);
+        let from = commit(&sandbox.local, "b.txt", "local\n", "Add b");
+        let remote = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        match sandbox.pull().unwrap() {
+            SyncOutcome::Rebased {
+                from: old, commits, ..
+            } => {
+                assert_eq!(old, from.to_string());
+                assert_eq!(commits, 1);
+            }
+            outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        let rebased = sandbox.local.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(rebased.message(), Some("Add b"));
+        assert_eq!(rebased.parent_ids().collect::<Vec<_>>(), vec![remote]);
+        assert_eq!(sandbox.read("a.txt"), "two\n");
+        assert_eq!(sandbox.read("b.txt"), "local\n");
+        assert!(dirty_paths(&sandbox.local).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rebase_conflict_leaves_the_branch_alone() {
+        let mut sandbox = Sandbox::new(SyncStrategy::Rebase, DirtyPolicy::Refuse);
+        let local = commit(&sandbox.local, "a.txt", "local\n", "Change a locally");
+        commit(&sandbox.upstream, "a.txt", "remote\n", "Change a remotely");
+
+        assert!(matches!(sandbox.pull(), Err(SyncError::Conflict(_))));
+        assert_eq!(head(&sandbox.local), local);
+        assert_eq!(sandbox.read("a.txt"), "local\n");
+        assert_eq!(sandbox.local.state(), git2::RepositoryState::Clean);
+    }
+
+    #[test]
+    fn merge_joins_local_and_remote_history() {
+        let mut sandbox = Sandbox::new(SyncStrategy::Merge, DirtyPolicy::Refuse);
+        let local = commit(&sandbox.local, "b.txt", "local\n", "Add b");
+        let remote = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        match sandbox.pull().unwrap() {
+            SyncOutcome::Merged { from, to, merged } => {
+                assert_eq!(from, local.to_string());
+                assert_eq!(merged, remote.to_string());
+                assert_eq!(to, head(&sandbox.local).to_string());
+            }
+            outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        let merge = sandbox.local.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(merge.parent_ids().collect::<Vec<_>>(), vec![local, remote]);
+        assert_eq!(sandbox.read("a.txt"), "two\n");
+        assert_eq!(sandbox.read("b.txt"), "local\n");
+    }
+
+    #[test]
+    fn merge_conflict_leaves_the_branch_alone() {
+        let mut sandbox = Sandbox::new(SyncStrategy::Merge, DirtyPolicy::Refuse);
+        let local = commit(&sandbox.local, "a.txt", "local\n", "Change a locally");
+        commit(&sandbox.upstream, "a.txt", "remote\n", "Change a remotely");
+
+        match sandbox.pull() {
+            Err(SyncError::Conflict(message)) => assert!(message.contains("a.txt")),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(outcome) => panic!("unexpected outcome: {}", outcome),
+        }
+        assert_eq!(head(&sandbox.local), local);
+        assert_eq!(sandbox.read("a.txt"), "local\n");
+    }
+
+    #[test]
+    fn reset_hard_discards_commits_ahead_of_the_remote() {
+        let mut sandbox = Sandbox::new(SyncStrategy::ResetHard, DirtyPolicy::Refuse);
+        let remote = head(&sandbox.upstream);
+        commit(&sandbox.local, "b.txt", "local\n", "Add b");
+        commit(&sandbox.local, "a.txt", "local\n", "Change a");
+
+        match sandbox.pull().unwrap() {
+            SyncOutcome::Reset { to, discarded, .. } => {
+                assert_eq!(to, remote.to_string());
+                assert_eq!(discarded, 2);
+            }
+            outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        assert_eq!(head(&sandbox.local), remote);
+        assert_eq!(sandbox.read("a.txt"), "one\n");
+        assert!(!sandbox.local.workdir().unwrap().join("b.txt").exists());
+    }
+
+    #[test]
+    fn reset_hard_mirrors_a_diverged_remote() {
+        let mut sandbox = Sandbox::new(SyncStrategy::ResetHard, DirtyPolicy::Refuse);
+        commit(&sandbox.local, "a.txt", "local\n", "Change a locally");
+        let remote = commit(&sandbox.upstream, "a.txt", "remote\n", "Change a remotely");
+
+        match sandbox.pull().unwrap() {
+            SyncOutcome::Reset { discarded, .. } => assert_eq!(discarded, 1),
+            outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        assert_eq!(head(&sandbox.local), remote);
+        assert_eq!(sandbox.read("a.txt"), "remote\n");
+    }
+
+    #[test]
+    fn refuse_keeps_local_edits_and_the_branch() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let before = head(&sandbox.local);
+        sandbox.write("a.txt", "edited\n");
+        commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        match sandbox.pull() {
+            Err(SyncError::DirtyWorkTree(paths)) => assert_eq!(paths, vec!["a.txt"]),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(outcome) => panic!("unexpected outcome: {}", outcome),
+        }
+        assert_eq!(head(&sandbox.local), before);
+        assert_eq!(sandbox.read("a.txt"), "edited\n");
+    }
+
+    #[test]
+    fn untracked_files_do_not_make_the_tree_dirty() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        sandbox.write("notes.txt", "untracked\n");
+        let remote = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        assert!(dirty_paths(&sandbox.local).unwrap().is_empty());
+        assert!(matches!(
+            sandbox.pull().unwrap(),
+            SyncOutcome::FastForwarded { .. }
+        ));
+        assert_eq!(head(&sandbox.local), remote);
+        assert_eq!(sandbox.read("notes.txt"), "untracked\n");
+    }
+
+    #[test]
+    fn discard_throws_local_edits_away() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Discard);
+        sandbox.write("a.txt", "edited\n");
+        let remote = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        assert!(matches!(
+            sandbox.pull().unwrap(),
+            SyncOutcome::FastForwarded { .. }
+        ));
+        assert_eq!(head(&sandbox.local), remote);
+        assert_eq!(sandbox.read("a.txt"), "two\n");
+        assert!(dirty_paths(&sandbox.local).unwrap().is_empty());
+    }
+
+    #[test]
+    fn stash_re_applies_local_edits_after_the_sync() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Stash);
+        commit(&sandbox.upstream, "b.txt", "one\n", "Add b");
+        sandbox.pull().unwrap();
+        sandbox.write("b.txt", "edited\n");
+        let remote = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        assert!(matches!(
+            sandbox.pull().unwrap(),
+            SyncOutcome::FastForwarded { .. }
+        ));
+        assert_eq!(head(&sandbox.local), remote);
+        assert_eq!(sandbox.read("a.txt"), "two\n");
+        assert_eq!(sandbox.read("b.txt"), "edited\n");
+        assert_eq!(sandbox.stash_count(), 0);
+    }
+
+    #[test]
+    fn stash_conflict_keeps_the_stash() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Stash);
+        sandbox.write("a.txt", "edited\n");
+        let remote = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        assert!(matches!(
+            sandbox.pull().unwrap(),
+            SyncOutcome::FastForwarded { .. }
+        ));
+        assert_eq!(head(&sandbox.local), remote);
+        // The local edit is not lost: it stays in stash@{0} for the user to resolve
+        assert_eq!(sandbox.stash_count(), 1);
+        let stash = sandbox.local.revparse_single("stash@{0}").unwrap();
+        let tree = stash.peel_to_commit().unwrap().tree().unwrap();
+        let blob = tree.get_name("a.txt").unwrap().to_object(&sandbox.local);
+        assert_eq!(blob.unwrap().peel_to_blob().unwrap().content(), b"edited\n");
+    }
+
+    #[test]
+    fn classifies_the_branch_against_the_remote() {
+        let sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let state = || {
+            fetch_target_branch(&sandbox.local, &sandbox.config).unwrap();
+            branch_state(&sandbox.local, &sandbox.config).unwrap()
+        };
+        assert_eq!(state(), BranchState::UpToDate);
+
+        commit(&sandbox.local, "b.txt", "local\n", "Add b");
+        assert_eq!(state(), BranchState::Ahead(1));
+
+        commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+        commit(&sandbox.upstream, "a.txt", "three\n", "Change a again");
+        assert_eq!(
+            state(),
+            BranchState::Diverged {
+                ahead: 1,
+                behind: 2
+            }
+        );
+
+        let base = sandbox.local.revparse_single("HEAD~1").unwrap();
+        sandbox.local.reset(&base, ResetType::Hard, None).unwrap();
+        assert_eq!(state(), BranchState::Behind(2));
+    }
+
+    #[test]
+    fn fetches_only_when_the_tracking_ref_is_stale() {
+        let sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let tracking = || {
+            sandbox
+                .local
+                .find_reference(&tracking_ref(&sandbox.config))
+                .unwrap()
+                .peel_to_commit()
+                .unwrap()
+                .id()
+        };
+        let before = tracking();
+        let remote = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+
+        fetch_if_stale(&sandbox.local, &sandbox.config, &before.to_string()).unwrap();
+        assert_eq!(tracking(), before);
+        fetch_if_stale(&sandbox.local, &sandbox.config, &remote.to_string()).unwrap();
+        assert_eq!(tracking(), remote);
+    }
+
+    #[test]
+    fn roll_back_restores_the_previous_commit() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let previous = head(&sandbox.local);
+        let synced = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+        sandbox.pull().unwrap();
+
+        let config = sandbox.config.clone();
+        match roll_back(&mut sandbox.local, &config, &previous.to_string()).unwrap() {
+            SyncOutcome::RolledBack { from, to } => {
+                assert_eq!(from, synced.to_string());
+                assert_eq!(to, previous.to_string());
+            }
+            outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        assert_eq!(head(&sandbox.local), previous);
+        assert_eq!(sandbox.read("a.txt"), "one\n");
+        assert_eq!(
+            current_branch(&sandbox.local).unwrap().as_deref(),
+            Some("main")
+        );
+        assert!(dirty_paths(&sandbox.local).unwrap().is_empty());
+    }
+
+    #[test]
+    fn roll_back_keeps_local_edits_with_stash() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Stash);
+        commit(&sandbox.upstream, "b.txt", "one\n", "Add b");
+        sandbox.pull().unwrap();
+        let previous = head(&sandbox.local);
+        commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+        sandbox.pull().unwrap();
+        sandbox.write("b.txt", "edited\n");
+
+        let config = sandbox.config.clone();
+        roll_back(&mut sandbox.local, &config, &previous.to_string()).unwrap();
+        assert_eq!(head(&sandbox.local), previous);
+        assert_eq!(sandbox.read("a.txt"), "one\n");
+        assert_eq!(sandbox.read("b.txt"), "edited\n");
+        assert_eq!(sandbox.stash_count(), 0);
+    }
+
+    #[test]
+    fn roll_back_refuses_to_touch_local_edits() {
+        let mut sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let previous = head(&sandbox.local);
+        let synced = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+        sandbox.pull().unwrap();
+        sandbox.write("a.txt", "edited\n");
+
+        let config = sandbox.config.clone();
+        let result = roll_back(&mut sandbox.local, &config, &previous.to_string());
+        assert!(matches!(result, Err(SyncError::DirtyWorkTree(_))));
+        assert_eq!(head(&sandbox.local), synced);
+        assert_eq!(sandbox.read("a.txt"), "edited\n");
+    }
+
+    #[test]
+    fn reports_what_was_fetched_when_the_remote_moved_on() {
+        let sandbox = Sandbox::new(SyncStrategy::FfOnly, DirtyPolicy::Refuse);
+        let reported = commit(&sandbox.upstream, "a.txt", "two\n", "Change a");
+        // Pushed after the API was asked, but before the fetch
+        let pushed = commit(&sandbox.upstream, "a.txt", "three\n", "Change a again");
+
+        fetch_if_stale(&sandbox.local, &sandbox.config, &reported.to_string()).unwrap();
+        assert_eq!(
+            fetched_sha(&sandbox.local, &sandbox.config).unwrap(),
+            pushed.to_string()
+        );
+    }
+}
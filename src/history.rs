@@ -0,0 +1,161 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// How a sync attempt ended.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncResult {
+    Synced,
+    Failed,
+    RolledBack,
+}
+
+// One line of the history file, written for every attempt to update a checkout.
+#[derive(Serialize, Deserialize)]
+pub struct SyncRecord {
+    pub timestamp: DateTime<Utc>,
+    pub repo: String,
+    // The checkout, which tells apart entries syncing the same repo into different directories.
+    pub path: String,
+    pub old_sha: String,
+    pub new_sha: String,
+    pub commits: usize,
+    pub authors: Vec<String>,
+    pub strategy: String,
+    pub duration_ms: u64,
+    pub result: SyncResult,
+    // What the sync did, or why it failed.
+    pub detail: String,
+}
+
+impl SyncRecord {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+}
+
+// Append-only log of sync attempts, one JSON record per line. Unlike app.log it is never
+// truncated, so it keeps an audit trail across restarts.
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        History { path: path.into() }
+    }
+
+    // Append a record. Each record is written with a single call on a file opened in append
+    // mode, so lines from different repos never interleave.
+    pub fn append(&self, record: &SyncRecord) {
+        let result = serde_json::to_string(record)
+            .map_err(std::io::Error::from)
+            .and_then(|line| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?
+                    .write_all(format!("{}\n", line).as_bytes())
+            });
+        if let Err(e) = result {
+            error!(
+                "Failed to append to sync history {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    // Read every record, skipping lines that cannot be parsed.
+    pub fn read(&self) -> std::io::Result<Vec<SyncRecord>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+// Which records the `history` subcommand prints.
+#[derive(Default)]
+pub struct HistoryFilter {
+    // Matches repos whose name or checkout path contains this, e.g. "owner/repo".
+    pub repo: Option<String>,
+    // First and last day to include, in UTC.
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl HistoryFilter {
+    // Parse `--repo <name>`, `--since <YYYY-MM-DD>` and `--until <YYYY-MM-DD>`.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut filter = HistoryFilter::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--repo" => filter.repo = Some(value()?.clone()),
+                "--since" => filter.since = Some(parse_date(value()?)?),
+                "--until" => filter.until = Some(parse_date(value()?)?),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, record: &SyncRecord) -> bool {
+        let day = record.timestamp.date_naive();
+        self.repo.as_ref().is_none_or(|repo| {
+            record.repo.contains(repo.as_str()) || record.path.contains(repo.as_str())
+        }) && self.since.is_none_or(|since| day >= since)
+            && self.until.is_none_or(|until| day <= until)
+    }
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("\"{}\" is not a date like 2024-01-31", value))
+}
+
+fn short(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+// Print the matching records, oldest first.
+pub fn print(history: &History, filter: &HistoryFilter) -> std::io::Result<()> {
+    for record in history.read()?.iter().filter(|r| filter.matches(r)) {
+        let result = match record.result {
+            SyncResult::Synced => "synced",
+            SyncResult::Failed => "FAILED",
+            SyncResult::RolledBack => "ROLLED BACK",
+        };
+        println!(
+            "{} UTC  {} ({})  {} -> {}  {} commit(s) by {}  {}  {:.1}s  {}: {}",
+            record.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            record.repo,
+            record.path,
+            short(&record.old_sha),
+            short(&record.new_sha),
+            record.commits,
+            if record.authors.is_empty() {
+                "-".to_string()
+            } else {
+                record.authors.join(", ")
+            },
+            record.strategy,
+            record.duration().as_secs_f64(),
+            result,
+            record.detail
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use crate::config::{HealthCheckConfig, HookConfig, HookFailurePolicy};
+use log::{error, info, warn};
+use reqwest::Client;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+// What a hook is told about the sync that triggered it, passed as REPO_SYNC_* variables.
+pub struct HookContext {
+    pub old_sha: String,
+    pub new_sha: String,
+    pub branch: String,
+    pub changed_files: Vec<String>,
+}
+
+impl HookContext {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("REPO_SYNC_OLD_SHA", self.old_sha.clone()),
+            ("REPO_SYNC_NEW_SHA", self.new_sha.clone()),
+            ("REPO_SYNC_BRANCH", self.branch.clone()),
+            ("REPO_SYNC_CHANGED_FILES", self.changed_files.join("\n")),
+        ]
+    }
+}
+
+// Hook commands go through the platform shell so pipes and quoting work as in a terminal.
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+fn log_output(name: &str, stream: &str, output: &[u8]) {
+    for line in String::from_utf8_lossy(output).lines() {
+        if stream == "stderr" {
+            warn!("[{}]   {}: {}", name, stream, line);
+        } else {
+            info!("[{}]   {}: {}", name, stream, line);
+        }
+    }
+}
+
+// Run one command in the repository directory, logging its output. Returns an error message
+// if it could not start, timed out or exited non-zero.
+async fn run_command(
+    name: &str,
+    command: &str,
+    timeout_seconds: u64,
+    dir: &Path,
+    env: &[(&'static str, String)],
+) -> Result<(), String> {
+    let child = shell(command)
+        .current_dir(dir)
+        .envs(env.iter().map(|(k, v)| (*k, v.as_str())))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to start: {}", e))?;
+
+    let limit = Duration::from_secs(timeout_seconds);
+    let output = match timeout(limit, child.wait_with_output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Err(format!("failed to run: {}", e)),
+        Err(_) => return Err(format!("timed out after {} seconds", timeout_seconds)),
+    };
+
+    log_output(name, "stdout", &output.stdout);
+    log_output(name, "stderr", &output.stderr);
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("exited with {}", output.status))
+    }
+}
+
+async fn run_hook(
+    name: &str,
+    hook: &HookConfig,
+    dir: &Path,
+    env: &[(&'static str, String)],
+) -> Result<(), String> {
+    info!("[{}] Running hook: {}", name, hook.command);
+    run_command(name, &hook.command, hook.timeout_seconds, dir, env).await
+}
+
+// Run the pre-sync gates in order, stopping at the first one that fails. Returns why the
+// update was vetoed, if it was.
+pub async fn run_pre_sync(
+    name: &str,
+    hooks: &[HookConfig],
+    dir: &Path,
+    context: &HookContext,
+) -> Result<(), String> {
+    let env = context.env();
+    for hook in hooks {
+        run_hook(name, hook, dir, &env)
+            .await
+            .map_err(|e| format!("pre-sync hook `{}` {}", hook.command, e))?;
+    }
+    Ok(())
+}
+
+// Run the post-sync hooks in order. A failing hook is logged and, depending on its
+// `on_failure` policy, either the remaining hooks still run or they are skipped. Returns
+// whether every hook that ran succeeded.
+pub async fn run_post_sync(
+    name: &str,
+    hooks: &[HookConfig],
+    dir: &Path,
+    context: &HookContext,
+) -> bool {
+    let env = context.env();
+    let mut all_succeeded = true;
+    for hook in hooks {
+        match run_hook(name, hook, dir, &env).await {
+            Ok(()) => info!("[{}] Hook succeeded: {}", name, hook.command),
+            Err(e) => {
+                error!("[{}] Hook failed: {}: {}", name, hook.command, e);
+                all_succeeded = false;
+                if hook.on_failure == HookFailurePolicy::Abort {
+                    warn!("[{}] Skipping the remaining post-sync hooks.", name);
+                    break;
+                }
+            }
+        }
+    }
+    all_succeeded
+}
+
+// Check that the service is healthy after a sync, after waiting `delay_seconds` for it to
+// restart. Returns why the check failed, if it did.
+pub async fn health_check(
+    name: &str,
+    check: &HealthCheckConfig,
+    dir: &Path,
+    context: &HookContext,
+    client: &Client,
+) -> Result<(), String> {
+    if check.delay_seconds > 0 {
+        tokio::time::sleep(Duration::from_secs(check.delay_seconds)).await;
+    }
+    if let Some(command) = &check.command {
+        info!("[{}] Running health check: {}", name, command);
+        return run_command(name, command, check.timeout_seconds, dir, &context.env()).await;
+    }
+    if let Some(url) = &check.url {
+        info!("[{}] Running health check: GET {}", name, url);
+        let response = client
+            .get(url)
+            .timeout(Duration::from_secs(check.timeout_seconds))
+            .send()
+            .await
+            .map_err(|e| format!("request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("answered with {}", response.status()));
+        }
+    }
+    Ok(())
+}
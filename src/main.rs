@@ -1,211 +1,258 @@
-use chrono::{DateTime, Utc};
+mod cli;
+mod config;
+mod daemon;
+mod git;
+mod history;
+mod hooks;
+mod provider;
+mod state;
+mod sync;
+mod webhook;
+
+use cli::{Cli, Command};
+use config::{load_config, Config, ConfigError};
+use daemon::{ConfigWatcher, Daemon};
 use git2::Repository;
-use log::{error, info};
-use reqwest::Client;
-use serde::Deserialize;
+use history::History;
+use log::{error, info, warn};
 use simplelog::*;
-use std::fs;
+use state::SyncState;
 use std::fs::File;
 use std::io::{self, Write};
-use std::process::Command;
-use std::time::{Duration, SystemTime};
-use tokio::time::sleep;
-
-#[derive(Deserialize)]
-struct Config {
-    github: GitHubConfig,
-    local_repo: LocalRepoConfig,
-}
-
-#[derive(Deserialize)]
-struct GitHubConfig {
-    owner: String,
-    repo: String,
-    target_branch: String,
-    access_token: Option<String>,
-}
-
-#[derive(Deserialize)]
-struct LocalRepoConfig {
-    path: String,
-    check_interval_seconds: u64,
-}
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+use sync::{Cycle, RepoSync, SyncContext};
 
-const GITHUB_API_URL: &str = "https://api.github.com/repos";
+// Whether log output goes to app.log rather than the terminal.
+static LOGGING_TO_FILE: AtomicBool = AtomicBool::new(false);
 
-#[derive(Deserialize)]
-struct GitHubCommit {
-    sha: String,
+// Log a fatal error and exit with `code`. When someone is watching, e.g. after a double click
+// on Windows, wait for Enter first so the console window does not vanish; headless runs under
+// systemd, cron or a container exit straight away.
+fn exit_with(cli: &Cli, code: i32, message: &str) -> ! {
+    error!("{}", message);
+    if LOGGING_TO_FILE.load(Ordering::Relaxed) {
+        eprintln!("{}", message);
+    }
+    if cli.interactive() {
+        println!("Press Enter to exit...");
+        let _ = io::stdout().flush();
+        let _ = io::stdin().read_line(&mut String::new());
+    }
+    std::process::exit(code);
 }
 
-// Utility function for formatting the time in a consistent format.
-fn format_time(time: SystemTime) -> String {
-    let datetime: DateTime<Utc> = time.into();
-    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+// Apply the command-line overrides to a freshly loaded config.
+fn apply_overrides(cli: &Cli, config: &mut Config) {
+    if let Some(interval) = cli.interval {
+        for repo in &mut config.repos {
+            repo.local_repo.check_interval_seconds = interval;
+        }
+    }
 }
 
-// Exponential backoff function to avoid hammering GitHub with too many requests in case of errors.
-fn exponential_backoff(attempt: u32) -> Duration {
-    let delay = 2u64.pow(attempt.min(6)); // Cap the delay to 64 seconds (2^6)
-    Duration::from_secs(delay)
+// Load the config named on the command line and apply the command-line overrides, exiting if
+// it cannot be used.
+fn load(cli: &Cli) -> Config {
+    let mut config = match load_config(&cli.config) {
+        Ok(config) => config,
+        Err(e @ ConfigError::Missing(_)) => {
+            exit_with(cli, cli::EXIT_CONFIG_MISSING, &e.to_string())
+        }
+        Err(e @ ConfigError::Invalid(_)) => {
+            exit_with(cli, cli::EXIT_CONFIG_INVALID, &e.to_string())
+        }
+    };
+    apply_overrides(cli, &mut config);
+    config
 }
 
-// Fetch the latest commit SHA from GitHub asynchronously using reqwest.
-async fn get_latest_commit_sha(config: &GitHubConfig) -> Option<String> {
-    let url = format!(
-        "{}/{}/{}/commits/{}",
-        GITHUB_API_URL, config.owner, config.repo, config.target_branch
-    );
-    let client = Client::new();
-
-    let mut request = client.get(&url).header("User-Agent", "rust-script");
-
-    if let Some(token) = &config.access_token {
-        request = request.header("Authorization", format!("token {}", token));
+// Build the handles shared by every repository, exiting if the config does not allow it.
+fn sync_context(cli: &Cli, config: &Config) -> SyncContext {
+    match SyncContext::new(config) {
+        Ok(context) => context,
+        Err(e) => exit_with(cli, cli::EXIT_CONFIG_INVALID, &e),
     }
+}
 
-    match request.send().await {
-        Ok(response) => match response.json::<GitHubCommit>().await {
-            Ok(commit) => {
-                info!("Fetched latest remote commit: {}", commit.sha);
-                Some(commit.sha)
-            }
+// `run`: spawn one polling task per configured repository and keep them going, reloading the
+// config whenever it changes. A config that fails to load is rejected and the running one kept.
+async fn run(cli: &Cli, config: Config, context: SyncContext) {
+    let mut daemon = Daemon::start(config, context);
+    let mut watcher = ConfigWatcher::new(&cli.config);
+    loop {
+        watcher.changed().await;
+        let mut config = match load_config(&cli.config) {
+            Ok(config) => config,
             Err(e) => {
-                error!("Failed to parse commit response: {}", e);
-                None
+                error!("Keeping the running configuration: {}", e);
+                continue;
             }
-        },
-        Err(e) => {
-            error!("Failed to send request: {}", e);
-            None
+        };
+        apply_overrides(cli, &mut config);
+        if let Err(e) = daemon.reload(config).await {
+            error!("Keeping the running configuration: {}", e);
         }
     }
 }
 
-// Get the local commit SHA from the local Git repository.
-fn get_local_commit_sha(repo: &Repository) -> Option<String> {
-    let head = repo.head().ok()?;
-    let commit = head.peel_to_commit().ok()?;
-    let local_commit = commit.id().to_string();
-    info!("Fetched local commit: {}", local_commit);
-    Some(local_commit)
-}
-
-// Pull the latest changes from the remote repository.
-fn pull_latest_changes(local_path: &str) {
-    info!("Pulling latest changes...");
-    let status = Command::new("git")
-        .arg("-C")
-        .arg(local_path)
-        .arg("pull")
-        .status();
-
-    match status {
-        Ok(status) if status.success() => info!("Successfully pulled latest changes."),
-        Ok(_) => error!("Failed to pull latest changes: Git command did not succeed."),
-        Err(e) => error!("Failed to execute git pull: {}", e),
+// `once`: check and sync every repository a single time. Returns the exit code listed in the
+// usage text.
+async fn once(config: Config, context: SyncContext) -> i32 {
+    let (mut failed, mut skipped) = (false, false);
+    for repo in config.repos {
+        let mut repo = match RepoSync::new(repo, &context) {
+            Ok(repo) => repo,
+            Err(e) => {
+                error!("{}", e);
+                failed = true;
+                continue;
+            }
+        };
+        let cycle = repo.check().await;
+        repo.save_state();
+        match cycle {
+            Cycle::UpToDate | Cycle::Synced => {}
+            Cycle::Skipped => skipped = true,
+            Cycle::Failed(_) => failed = true,
+        }
+    }
+    if failed {
+        cli::EXIT_FAILURE
+    } else if skipped {
+        cli::EXIT_SKIPPED
+    } else {
+        0
     }
 }
 
-// Load the configuration from the config.toml file.
-fn load_config() -> Config {
-    let config_content = match fs::read_to_string("config.toml") {
-        Ok(content) => {
-            info!("Config file read successfully.");
-            content
-        }
-        Err(e) => {
-            error!("Failed to read config.toml: {}", e);
-            println!("Press Enter to exit...");
-            io::stdout().flush().unwrap();
-            let _ = io::stdin().read_line(&mut String::new());
-            std::process::exit(1);
+// `status`: print what each repository's state file says, plus where its checkout is now.
+fn status(config: &Config) {
+    let state_dir = Path::new(&config.state_dir);
+    for repo in &config.repos {
+        let name = repo.name();
+        let state = SyncState::load(state_dir, &repo.local_repo.path);
+        println!("{}", name);
+        println!("  path:                 {}", repo.local_repo.path);
+        let head = Repository::open(&repo.local_repo.path)
+            .and_then(|r| {
+                let head = r.head()?;
+                let sha = head.peel_to_commit()?.id().to_string();
+                Ok(format!(
+                    "{} on {}",
+                    sha,
+                    head.shorthand().unwrap_or("(unknown)")
+                ))
+            })
+            .unwrap_or_else(|e| format!("unavailable ({})", e.message()));
+        println!("  checkout:             {}", head);
+        println!(
+            "  last synced commit:   {}",
+            state.last_synced_sha.as_deref().unwrap_or("never")
+        );
+        println!(
+            "  last change:          {}",
+            state
+                .last_sync_time
+                .map(|time| format!("{} UTC", sync::format_time(SystemTime::from(time))))
+                .unwrap_or_else(|| "never".to_string())
+        );
+        println!(
+            "  last error:           {}",
+            state.last_error.as_deref().unwrap_or("none")
+        );
+        println!("  consecutive failures: {}", state.consecutive_failures);
+        if let Some(bad_commit) = &state.bad_commit {
+            println!("  skipped commit:       {}", bad_commit);
         }
-    };
+    }
+}
 
-    match toml::from_str(&config_content) {
-        Ok(config) => {
-            info!("Config file parsed successfully.");
-            config
-        }
-        Err(e) => {
-            error!("Failed to parse config.toml: {}", e);
-            println!("Press Enter to exit...");
-            io::stdout().flush().unwrap();
-            let _ = io::stdin().read_line(&mut String::new());
-            std::process::exit(1);
+// `check-config`: load the config and build everything a sync needs from it, without
+// touching any repository.
+fn check_config(cli: &Cli, config: &Config) {
+    let context = sync_context(cli, config);
+    for repo in &config.repos {
+        if let Err(e) = RepoSync::new(repo.clone(), &context) {
+            exit_with(cli, cli::EXIT_CONFIG_INVALID, &e);
         }
     }
+    println!(
+        "Configuration is valid: {} repository(s).",
+        config.repos.len()
+    );
 }
 
-// Main async function with exponential backoff and time formatting.
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize logging
-    CombinedLogger::init(vec![WriteLogger::new(
-        LevelFilter::Info,
-        ConfigBuilder::new().build(),
-        File::create("app.log").unwrap(),
-    )])?;
-
-    info!("Starting application");
-
-    // Load config
-    let config = load_config();
-
-    let check_interval = Duration::from_secs(config.local_repo.check_interval_seconds);
-    let mut last_change_time = SystemTime::now();
-
-    let mut backoff_attempt = 0;
+async fn main() {
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(cli::EXIT_USAGE);
+        }
+    };
 
-    // Main loop for checking repository status
-    loop {
-        let repo = match Repository::open(&config.local_repo.path) {
-            Ok(repo) => repo,
-            Err(e) => {
-                error!("Failed to open local repository: {}", e);
-                sleep(check_interval).await;
-                continue;
+    match &cli.command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::Run | Command::Once => {
+            // Initialize logging, next to the config file so it does not depend on the cwd. If
+            // that is not writable, e.g. in a read-only container, log to the terminal instead
+            let level = cli.log_level.unwrap_or(LevelFilter::Info);
+            let log_file = cli.config.parent().unwrap_or(Path::new("")).join("app.log");
+            match File::create(&log_file) {
+                Ok(file) => {
+                    CombinedLogger::init(vec![WriteLogger::new(
+                        level,
+                        ConfigBuilder::new().build(),
+                        file,
+                    )])
+                    .unwrap();
+                    LOGGING_TO_FILE.store(true, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    SimpleLogger::init(level, ConfigBuilder::new().build()).unwrap();
+                    warn!(
+                        "Cannot write {}: {}. Logging to the terminal instead.",
+                        log_file.display(),
+                        e
+                    );
+                }
             }
-        };
 
-        let latest_remote_commit = match get_latest_commit_sha(&config.github).await {
-            Some(commit) => commit,
-            None => {
-                error!("Failed to get latest remote commit.");
-                sleep(exponential_backoff(backoff_attempt)).await;
-                backoff_attempt += 1;
-                continue;
-            }
-        };
+            info!("Starting application");
 
-        let local_commit = match get_local_commit_sha(&repo) {
-            Some(commit) => commit,
-            None => {
-                error!("Failed to get local commit.");
-                sleep(exponential_backoff(backoff_attempt)).await;
-                backoff_attempt += 1;
-                continue;
+            let config = load(&cli);
+            let context = sync_context(&cli, &config);
+            if let Command::Once = cli.command {
+                std::process::exit(once(config, context).await);
+            }
+            run(&cli, config, context).await;
+        }
+        // The other commands report to the terminal and leave the daemon's app.log alone
+        Command::Status | Command::CheckConfig | Command::History(_) => {
+            SimpleLogger::init(
+                cli.log_level.unwrap_or(LevelFilter::Warn),
+                ConfigBuilder::new().build(),
+            )
+            .unwrap();
+            let config = load(&cli);
+            match &cli.command {
+                Command::Status => status(&config),
+                Command::CheckConfig => check_config(&cli, &config),
+                Command::History(filter) => {
+                    let history = History::new(&config.history_file);
+                    if let Err(e) = history::print(&history, filter) {
+                        exit_with(
+                            &cli,
+                            cli::EXIT_FAILURE,
+                            &format!("Failed to read {}: {}", config.history_file, e),
+                        );
+                    }
+                }
+                _ => unreachable!(),
             }
-        };
-
-        // If new changes are detected, pull the latest changes
-        if latest_remote_commit != local_commit {
-            info!("New changes detected. Pulling updates...");
-            pull_latest_changes(&config.local_repo.path);
-            last_change_time = SystemTime::now();
-            backoff_attempt = 0; // Reset backoff after successful operation
-        } else {
-            let elapsed = last_change_time.elapsed()?.as_secs();
-            let formatted_time = format_time(last_change_time);
-            print!(
-                "\rNo new changes since {} UTC. Elapsed time: {} seconds.",
-                formatted_time, elapsed
-            );
-            io::stdout().flush()?; // Ensure the output is flushed
         }
-
-        // Sleep for the configured interval before the next check
-        sleep(check_interval).await;
     }
 }
@@ -0,0 +1,111 @@
+use super::{get_json, Comparison, ProviderError, RemoteCommit, RemoteProvider, RepoApi, Tag};
+use async_trait::async_trait;
+use log::info;
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+
+pub const BITBUCKET_API_URL: &str = "https://api.bitbucket.org/2.0";
+
+#[derive(Deserialize)]
+struct BitbucketRef {
+    name: String,
+    target: BitbucketTarget,
+}
+
+#[derive(Deserialize)]
+struct BitbucketTarget {
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct BitbucketCommit {
+    hash: String,
+    author: BitbucketAuthor,
+}
+
+#[derive(Deserialize)]
+struct BitbucketAuthor {
+    raw: String,
+    user: Option<BitbucketUser>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketUser {
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+struct BitbucketPage<T> {
+    values: Vec<T>,
+}
+
+pub struct Bitbucket {
+    api: RepoApi,
+}
+
+impl Bitbucket {
+    pub(super) fn new(api: RepoApi) -> Self {
+        Bitbucket { api }
+    }
+
+    // Bitbucket calls the owner a workspace.
+    fn get(&self, path: &[&str]) -> RequestBuilder {
+        let mut segments = vec![
+            "repositories",
+            self.api.owner.as_str(),
+            self.api.repo.as_str(),
+        ];
+        segments.extend_from_slice(path);
+        let request = self.api.get(&segments);
+        match &self.api.access_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for Bitbucket {
+    async fn latest_commit_sha(&self, branch: &str) -> Result<String, ProviderError> {
+        let branch: BitbucketRef = self
+            .api
+            .get_json_cached(self.get(&["refs", "branches", branch]))
+            .await?;
+        info!("Fetched latest remote commit: {}", branch.target.hash);
+        Ok(branch.target.hash)
+    }
+
+    // Only the first page of commits is listed, and `behind_by` is always zero.
+    async fn compare(&self, base: &str, head: &str) -> Result<Comparison, ProviderError> {
+        let page: BitbucketPage<BitbucketCommit> =
+            get_json(self.get(&["commits", head]).query(&[("exclude", base)])).await?;
+        Ok(Comparison {
+            ahead_by: page.values.len(),
+            behind_by: 0,
+            commits: page
+                .values
+                .into_iter()
+                .map(|c| RemoteCommit {
+                    sha: c.hash,
+                    author: c
+                        .author
+                        .user
+                        .map(|u| u.display_name)
+                        .unwrap_or(c.author.raw),
+                })
+                .collect(),
+        })
+    }
+
+    async fn list_tags(&self) -> Result<Vec<Tag>, ProviderError> {
+        let page: BitbucketPage<BitbucketRef> = get_json(self.get(&["refs", "tags"])).await?;
+        Ok(page
+            .values
+            .into_iter()
+            .map(|t| Tag {
+                name: t.name,
+                sha: t.target.hash,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,69 @@
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// A response body together with the validators needed to ask the server whether it changed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedResponse {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: String,
+}
+
+// Conditional request cache keyed by URL and shared by every repository task. It is written
+// to disk after each update so a restart does not start with an empty cache and spend quota
+// on responses we already have.
+pub struct ResponseCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl ResponseCache {
+    // Load the cache file, starting empty if it is missing or unreadable.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!(
+                    "Ignoring unreadable response cache {}: {}",
+                    path.display(),
+                    e
+                );
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        ResponseCache {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Option<CachedResponse> {
+        self.entries.lock().unwrap().get(url).cloned()
+    }
+
+    pub fn insert(&self, url: String, response: CachedResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(url, response);
+        if let Err(e) = self.save(&entries) {
+            error!(
+                "Failed to write response cache {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    // Write to a temporary file and rename it over the old one, so a crash mid-write never
+    // leaves a truncated cache behind.
+    fn save(&self, entries: &HashMap<String, CachedResponse>) -> std::io::Result<()> {
+        let content = serde_json::to_string_pretty(entries)?;
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
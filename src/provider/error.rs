@@ -0,0 +1,229 @@
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+// Why a provider call failed.
+#[derive(Debug)]
+pub enum ProviderError {
+    // 401: the access token is missing, expired or wrong.
+    Unauthorized(String),
+    // 403 that is not a rate limit: the token cannot access the repository.
+    Forbidden(String),
+    // 404: the owner, repo or branch does not exist, or is private and no token was given.
+    NotFound(String),
+    // 409: the repository exists but has no commits yet.
+    EmptyRepository(String),
+    // The API quota is used up; retry once `retry_after` has passed.
+    RateLimited { retry_after: Duration },
+    // 5xx: the provider is having trouble on its side.
+    Server { status: u16, message: String },
+    // Any other unexpected status.
+    Status { status: u16, message: String },
+    // No response arrived: DNS, TLS, connection failures and timeouts.
+    Network(String),
+    // A response arrived but was not what the API documents.
+    InvalidResponse(String),
+    // Listing refs over the git protocol failed.
+    Git(String),
+    // The provider has no API for this operation.
+    Unsupported(&'static str),
+}
+
+impl ProviderError {
+    // Errors that will not go away by retrying, only by fixing the configuration.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            ProviderError::Unauthorized(_)
+                | ProviderError::Forbidden(_)
+                | ProviderError::NotFound(_)
+        )
+    }
+
+    // Classify a non-success response, keeping the message from the API's error body.
+    pub async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let message = api_message(&body);
+        match status {
+            StatusCode::UNAUTHORIZED => ProviderError::Unauthorized(message),
+            StatusCode::FORBIDDEN => ProviderError::Forbidden(message),
+            StatusCode::NOT_FOUND => ProviderError::NotFound(message),
+            StatusCode::CONFLICT => ProviderError::EmptyRepository(message),
+            status if status.is_server_error() => ProviderError::Server {
+                status: status.as_u16(),
+                message,
+            },
+            status => ProviderError::Status {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            ProviderError::InvalidResponse(e.to_string())
+        } else {
+            ProviderError::Network(e.to_string())
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Unauthorized(msg) => write!(
+                f,
+                "authentication failed (401): {}; check access_token",
+                msg
+            ),
+            ProviderError::Forbidden(msg) => write!(
+                f,
+                "access denied (403): {}; check the access_token permissions",
+                msg
+            ),
+            ProviderError::NotFound(msg) => write!(
+                f,
+                "not found (404): {}; check owner, repo and target_branch",
+                msg
+            ),
+            ProviderError::EmptyRepository(msg) => {
+                write!(f, "repository is empty (409): {}", msg)
+            }
+            ProviderError::RateLimited { retry_after } => write!(
+                f,
+                "API rate limit exceeded, resets in {} seconds",
+                retry_after.as_secs()
+            ),
+            ProviderError::Server { status, message } => {
+                write!(f, "provider server error ({}): {}", status, message)
+            }
+            ProviderError::Status { status, message } => {
+                write!(f, "unexpected API response ({}): {}", status, message)
+            }
+            ProviderError::Network(msg) => write!(f, "request failed: {}", msg),
+            ProviderError::InvalidResponse(msg) => {
+                write!(f, "failed to parse API response: {}", msg)
+            }
+            ProviderError::Git(msg) => write!(f, "{}", msg),
+            ProviderError::Unsupported(operation) => {
+                write!(f, "{} is not supported by this provider", operation)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: Option<String>,
+    error: Option<ErrorDetail>,
+}
+
+// Bitbucket nests the message as `{"error": {"message": ...}}`, GitLab sometimes uses a bare
+// string for `error`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorDetail {
+    Message { message: String },
+    Text(String),
+}
+
+// The human-readable message from an API error body, falling back to the raw body.
+fn api_message(body: &str) -> String {
+    if let Ok(parsed) = serde_json::from_str::<ErrorBody>(body) {
+        let message = match (parsed.message, parsed.error) {
+            (Some(message), _) => Some(message),
+            (None, Some(ErrorDetail::Message { message })) => Some(message),
+            (None, Some(ErrorDetail::Text(text))) => Some(text),
+            (None, None) => None,
+        };
+        if let Some(message) = message {
+            return message;
+        }
+    }
+    let body = body.trim();
+    if body.is_empty() {
+        "no error message".to_string()
+    } else {
+        body.chars().take(200).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_github_and_gitea_messages() {
+        assert_eq!(
+            api_message(r#"{"message": "Bad credentials"}"#),
+            "Bad credentials"
+        );
+    }
+
+    #[test]
+    fn reads_nested_bitbucket_messages() {
+        let body = r#"{"type": "error", "error": {"message": "Repository not found"}}"#;
+        assert_eq!(api_message(body), "Repository not found");
+    }
+
+    #[test]
+    fn reads_gitlab_error_strings() {
+        assert_eq!(
+            api_message(r#"{"error": "invalid_token"}"#),
+            "invalid_token"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_raw_body() {
+        assert_eq!(
+            api_message("  Service Unavailable\n"),
+            "Service Unavailable"
+        );
+        assert_eq!(
+            api_message(r#"{"documentation_url": "x"}"#),
+            r#"{"documentation_url": "x"}"#
+        );
+        assert_eq!(api_message(""), "no error message");
+    }
+
+    #[test]
+    fn truncates_long_bodies() {
+        assert_eq!(api_message(&"x".repeat(1000)).len(), 200);
+    }
+
+    #[tokio::test]
+    async fn classifies_statuses() {
+        let error = |status: u16| async move {
+            let response = hyper::Response::builder()
+                .status(status)
+                .body(r#"{"message": "nope"}"#)
+                .unwrap();
+            ProviderError::from_response(Response::from(response)).await
+        };
+        assert!(matches!(error(401).await, ProviderError::Unauthorized(m) if m == "nope"));
+        assert!(matches!(error(403).await, ProviderError::Forbidden(_)));
+        assert!(matches!(error(404).await, ProviderError::NotFound(_)));
+        assert!(matches!(
+            error(409).await,
+            ProviderError::EmptyRepository(_)
+        ));
+        assert!(matches!(
+            error(502).await,
+            ProviderError::Server { status: 502, .. }
+        ));
+        assert!(matches!(
+            error(418).await,
+            ProviderError::Status { status: 418, .. }
+        ));
+        assert!(error(401).await.is_permanent());
+        assert!(!error(502).await.is_permanent());
+    }
+}
@@ -0,0 +1,107 @@
+use super::{Comparison, ProviderError, RemoteProvider, Tag};
+use crate::config::RepoConfig;
+use crate::git::{proxy_options, remote_callbacks};
+use async_trait::async_trait;
+use git2::{Direction, Repository};
+use log::info;
+
+// Reads refs straight from the configured git remote, the equivalent of `git ls-remote`. Works
+// with any URL libgit2 can reach, including plain git servers, file:// URLs and local bare
+// repositories, at the cost of having no compare API.
+pub struct GitRemote {
+    config: RepoConfig,
+}
+
+// A ref advertised by the remote, with annotated tags already peeled to their commit.
+struct RemoteRef {
+    name: String,
+    sha: String,
+}
+
+impl GitRemote {
+    pub(super) fn new(config: RepoConfig) -> Self {
+        GitRemote { config }
+    }
+
+    // Connect to the remote and list its refs. The connection is blocking, so it runs on the
+    // blocking thread pool.
+    async fn list_refs(&self) -> Result<Vec<RemoteRef>, ProviderError> {
+        let config = self.config.clone();
+        let result = tokio::task::spawn_blocking(move || -> Result<_, git2::Error> {
+            let repo = Repository::open(&config.local_repo.path)?;
+            let mut remote = repo.find_remote(&config.local_repo.remote)?;
+            let connection = remote.connect_auth(
+                Direction::Fetch,
+                Some(remote_callbacks(&config)),
+                Some(proxy_options(&config)),
+            )?;
+
+            let mut refs: Vec<RemoteRef> = Vec::new();
+            for head in connection.list()? {
+                // `refs/tags/v1^{}` is the commit an annotated tag points at.
+                if let Some(peeled) = head.name().strip_suffix("^{}") {
+                    refs.retain(|r| r.name != peeled);
+                    refs.push(RemoteRef {
+                        name: peeled.to_string(),
+                        sha: head.oid().to_string(),
+                    });
+                } else if !refs.iter().any(|r| r.name == head.name()) {
+                    refs.push(RemoteRef {
+                        name: head.name().to_string(),
+                        sha: head.oid().to_string(),
+                    });
+                }
+            }
+            Ok(refs)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(refs)) => Ok(refs),
+            Ok(Err(e)) => Err(ProviderError::Git(format!(
+                "failed to list refs on remote {}: {}",
+                self.config.local_repo.remote, e
+            ))),
+            Err(e) => Err(ProviderError::Git(format!(
+                "failed to list remote refs: {}",
+                e
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for GitRemote {
+    async fn latest_commit_sha(&self, branch: &str) -> Result<String, ProviderError> {
+        let refname = format!("refs/heads/{}", branch);
+        let refs = self.list_refs().await?;
+        match refs.into_iter().find(|r| r.name == refname) {
+            Some(head) => {
+                info!("Fetched latest remote commit: {}", head.sha);
+                Ok(head.sha)
+            }
+            None => Err(ProviderError::NotFound(format!(
+                "branch {} not found on remote {}",
+                branch, self.config.local_repo.remote
+            ))),
+        }
+    }
+
+    // The git protocol has no compare operation without fetching.
+    async fn compare(&self, _base: &str, _head: &str) -> Result<Comparison, ProviderError> {
+        Err(ProviderError::Unsupported("compare"))
+    }
+
+    async fn list_tags(&self) -> Result<Vec<Tag>, ProviderError> {
+        let refs = self.list_refs().await?;
+        Ok(refs
+            .into_iter()
+            .filter_map(|r| {
+                r.name.strip_prefix("refs/tags/").map(|name| Tag {
+                    name: name.to_string(),
+                    sha: r.sha.clone(),
+                })
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,110 @@
+use super::{get_json, Comparison, ProviderError, RemoteCommit, RemoteProvider, RepoApi, Tag};
+use async_trait::async_trait;
+use log::info;
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+
+pub const GITEA_API_URL: &str = "https://gitea.com/api/v1";
+
+#[derive(Deserialize)]
+struct GiteaBranch {
+    commit: GiteaBranchCommit,
+}
+
+#[derive(Deserialize)]
+struct GiteaBranchCommit {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaCommit {
+    sha: String,
+    commit: GiteaCommitDetail,
+}
+
+#[derive(Deserialize)]
+struct GiteaCommitDetail {
+    author: GiteaAuthor,
+}
+
+#[derive(Deserialize)]
+struct GiteaAuthor {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct GiteaComparison {
+    commits: Vec<GiteaCommit>,
+}
+
+#[derive(Deserialize)]
+struct GiteaTag {
+    name: String,
+    commit: GiteaTagCommit,
+}
+
+#[derive(Deserialize)]
+struct GiteaTagCommit {
+    sha: String,
+}
+
+pub struct Gitea {
+    api: RepoApi,
+}
+
+impl Gitea {
+    pub(super) fn new(api: RepoApi) -> Self {
+        Gitea { api }
+    }
+
+    fn get(&self, path: &[&str]) -> RequestBuilder {
+        let mut segments = vec!["repos", self.api.owner.as_str(), self.api.repo.as_str()];
+        segments.extend_from_slice(path);
+        let request = self.api.get(&segments);
+        match &self.api.access_token {
+            Some(token) => request.header("Authorization", format!("token {}", token)),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for Gitea {
+    async fn latest_commit_sha(&self, branch: &str) -> Result<String, ProviderError> {
+        let branch: GiteaBranch = self
+            .api
+            .get_json_cached(self.get(&["branches", branch]))
+            .await?;
+        info!("Fetched latest remote commit: {}", branch.commit.id);
+        Ok(branch.commit.id)
+    }
+
+    // Gitea only reports the commits in `base...head`, so `behind_by` is always zero.
+    async fn compare(&self, base: &str, head: &str) -> Result<Comparison, ProviderError> {
+        let comparison: GiteaComparison =
+            get_json(self.get(&["compare", &format!("{}...{}", base, head)])).await?;
+        Ok(Comparison {
+            ahead_by: comparison.commits.len(),
+            behind_by: 0,
+            commits: comparison
+                .commits
+                .into_iter()
+                .map(|c| RemoteCommit {
+                    sha: c.sha,
+                    author: c.commit.author.name,
+                })
+                .collect(),
+        })
+    }
+
+    async fn list_tags(&self) -> Result<Vec<Tag>, ProviderError> {
+        let tags: Vec<GiteaTag> = get_json(self.get(&["tags"])).await?;
+        Ok(tags
+            .into_iter()
+            .map(|t| Tag {
+                name: t.name,
+                sha: t.commit.sha,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,101 @@
+use super::{get_json, Comparison, ProviderError, RemoteCommit, RemoteProvider, RepoApi, Tag};
+use async_trait::async_trait;
+use log::info;
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+#[derive(Deserialize)]
+struct GitHubCommit {
+    sha: String,
+    commit: Option<GitHubCommitDetail>,
+}
+
+#[derive(Deserialize)]
+struct GitHubCommitDetail {
+    author: GitHubAuthor,
+}
+
+#[derive(Deserialize)]
+struct GitHubAuthor {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubComparison {
+    ahead_by: usize,
+    behind_by: usize,
+    commits: Vec<GitHubCommit>,
+}
+
+#[derive(Deserialize)]
+struct GitHubTag {
+    name: String,
+    commit: GitHubTagCommit,
+}
+
+#[derive(Deserialize)]
+struct GitHubTagCommit {
+    sha: String,
+}
+
+pub struct GitHub {
+    api: RepoApi,
+}
+
+impl GitHub {
+    pub(super) fn new(api: RepoApi) -> Self {
+        GitHub { api }
+    }
+
+    fn get(&self, path: &[&str]) -> RequestBuilder {
+        let mut segments = vec!["repos", self.api.owner.as_str(), self.api.repo.as_str()];
+        segments.extend_from_slice(path);
+        let request = self.api.get(&segments);
+        match &self.api.access_token {
+            Some(token) => request.header("Authorization", format!("token {}", token)),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for GitHub {
+    async fn latest_commit_sha(&self, branch: &str) -> Result<String, ProviderError> {
+        let commit: GitHubCommit = self
+            .api
+            .get_json_cached(self.get(&["commits", branch]))
+            .await?;
+        info!("Fetched latest remote commit: {}", commit.sha);
+        Ok(commit.sha)
+    }
+
+    async fn compare(&self, base: &str, head: &str) -> Result<Comparison, ProviderError> {
+        let comparison: GitHubComparison =
+            get_json(self.get(&["compare", &format!("{}...{}", base, head)])).await?;
+        Ok(Comparison {
+            ahead_by: comparison.ahead_by,
+            behind_by: comparison.behind_by,
+            commits: comparison
+                .commits
+                .into_iter()
+                .map(|c| RemoteCommit {
+                    sha: c.sha,
+                    author: c.commit.map(|d| d.author.name).unwrap_or_default(),
+                })
+                .collect(),
+        })
+    }
+
+    async fn list_tags(&self) -> Result<Vec<Tag>, ProviderError> {
+        let tags: Vec<GitHubTag> = get_json(self.get(&["tags"])).await?;
+        Ok(tags
+            .into_iter()
+            .map(|t| Tag {
+                name: t.name,
+                sha: t.commit.sha,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,98 @@
+use super::{get_json, Comparison, ProviderError, RemoteCommit, RemoteProvider, RepoApi, Tag};
+use async_trait::async_trait;
+use log::info;
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+
+pub const GITLAB_API_URL: &str = "https://gitlab.com/api/v4";
+
+#[derive(Deserialize)]
+struct GitLabBranch {
+    commit: GitLabCommit,
+}
+
+#[derive(Deserialize)]
+struct GitLabCommit {
+    id: String,
+    #[serde(default)]
+    author_name: String,
+}
+
+#[derive(Deserialize)]
+struct GitLabComparison {
+    commits: Vec<GitLabCommit>,
+}
+
+#[derive(Deserialize)]
+struct GitLabTag {
+    name: String,
+    commit: GitLabCommit,
+}
+
+pub struct GitLab {
+    api: RepoApi,
+}
+
+impl GitLab {
+    pub(super) fn new(api: RepoApi) -> Self {
+        GitLab { api }
+    }
+
+    // GitLab addresses projects by their full path as a single segment, which the URL builder
+    // encodes as e.g. `group%2Fproject`.
+    fn get(&self, path: &[&str]) -> RequestBuilder {
+        let project = format!("{}/{}", self.api.owner, self.api.repo);
+        let mut segments = vec!["projects", project.as_str(), "repository"];
+        segments.extend_from_slice(path);
+        let request = self.api.get(&segments);
+        match &self.api.access_token {
+            Some(token) => request.header("PRIVATE-TOKEN", token),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl RemoteProvider for GitLab {
+    async fn latest_commit_sha(&self, branch: &str) -> Result<String, ProviderError> {
+        let branch: GitLabBranch = self
+            .api
+            .get_json_cached(self.get(&["branches", branch]))
+            .await?;
+        info!("Fetched latest remote commit: {}", branch.commit.id);
+        Ok(branch.commit.id)
+    }
+
+    // GitLab only reports the commits in `base..head`, so `behind_by` is always zero.
+    async fn compare(&self, base: &str, head: &str) -> Result<Comparison, ProviderError> {
+        let comparison: GitLabComparison = get_json(self.get(&["compare"]).query(&[
+            ("from", base),
+            ("to", head),
+            ("straight", "false"),
+        ]))
+        .await?;
+        Ok(Comparison {
+            ahead_by: comparison.commits.len(),
+            behind_by: 0,
+            commits: comparison
+                .commits
+                .into_iter()
+                .map(|c| RemoteCommit {
+                    sha: c.id,
+                    author: c.author_name,
+                })
+                .collect(),
+        })
+    }
+
+    async fn list_tags(&self) -> Result<Vec<Tag>, ProviderError> {
+        let tags: Vec<GitLabTag> = get_json(self.get(&["tags"])).await?;
+        Ok(tags
+            .into_iter()
+            .map(|t| Tag {
+                name: t.name,
+                sha: t.commit.id,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::HttpConfig;
+use reqwest::{Certificate, Client, ClientBuilder, NoProxy, Proxy};
+use std::fs;
+use std::time::Duration;
+
+// Build the HTTP client shared by every repository task, so connections are pooled and kept
+// alive between checks instead of being set up again on every request.
+pub fn build_client(config: &HttpConfig) -> Result<Client, String> {
+    let mut builder = builder(config)?;
+
+    // Without an explicit proxy, reqwest already honors HTTP_PROXY, HTTPS_PROXY and NO_PROXY.
+    if let Some(url) = &config.proxy {
+        let proxy = Proxy::all(url)
+            .map_err(|e| format!("proxy \"{}\" is not a valid proxy URL: {}", url, e))?
+            .no_proxy(NoProxy::from_env());
+        builder = builder.proxy(proxy);
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("failed to build HTTP client: {}", e))
+}
+
+// Build the client for health check URLs. These usually point at the host itself, e.g.
+// http://localhost:8080/health, so they never go through a proxy.
+pub fn build_health_check_client(config: &HttpConfig) -> Result<Client, String> {
+    builder(config)?
+        .no_proxy()
+        .build()
+        .map_err(|e| format!("failed to build HTTP client: {}", e))
+}
+
+// Timeouts and root certificates shared by both clients.
+fn builder(config: &HttpConfig) -> Result<ClientBuilder, String> {
+    let mut builder = Client::builder()
+        .user_agent("rust-script")
+        .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+        .timeout(Duration::from_secs(config.timeout_seconds))
+        .tcp_keepalive(Duration::from_secs(60));
+
+    // Extra roots for corporate TLS interception, on top of the system store.
+    if let Some(path) = &config.ca_bundle {
+        let pem =
+            fs::read(path).map_err(|e| format!("failed to read ca_bundle {}: {}", path, e))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("ca_bundle {} is not a valid PEM bundle: {}", path, e))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn health_checks_skip_the_proxy() {
+        // Answers every connection with 200, like a service's health endpoint
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await;
+            }
+        });
+        // Nothing listens on the discard port, so anything sent through the proxy fails
+        let config = HttpConfig {
+            proxy: Some("http://127.0.0.1:9".to_string()),
+            ..HttpConfig::default()
+        };
+
+        let health_check_client = build_health_check_client(&config).unwrap();
+        let response = health_check_client.get(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+        assert!(build_client(&config)
+            .unwrap()
+            .get(&url)
+            .send()
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,216 @@
+mod bitbucket;
+mod cache;
+mod error;
+mod git_remote;
+mod gitea;
+mod github;
+mod gitlab;
+mod http;
+mod rate_limit;
+
+use crate::config::{GitHubConfig, ProviderKind, RepoConfig};
+use async_trait::async_trait;
+use cache::CachedResponse;
+use log::info;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+pub use bitbucket::Bitbucket;
+pub use cache::ResponseCache;
+pub use error::ProviderError;
+pub use git_remote::GitRemote;
+pub use gitea::Gitea;
+pub use github::GitHub;
+pub use gitlab::GitLab;
+pub use http::{build_client, build_health_check_client};
+
+// A commit as reported by the hosting provider's API.
+pub struct RemoteCommit {
+    pub sha: String,
+    pub author: String,
+}
+
+// The commits on `head` that are not on `base`, plus how far apart the two are.
+pub struct Comparison {
+    pub ahead_by: usize,
+    pub behind_by: usize,
+    pub commits: Vec<RemoteCommit>,
+}
+
+pub struct Tag {
+    pub name: String,
+    pub sha: String,
+}
+
+// The operations the sync loop needs from a hosting provider's REST API.
+#[async_trait]
+pub trait RemoteProvider: Send + Sync {
+    // Latest commit SHA on `branch`.
+    async fn latest_commit_sha(&self, branch: &str) -> Result<String, ProviderError>;
+
+    // Compare two commits, listing the commits reachable from `head` but not from `base`.
+    async fn compare(&self, base: &str, head: &str) -> Result<Comparison, ProviderError>;
+
+    // All tags in the repository with the commit each one points at.
+    async fn list_tags(&self) -> Result<Vec<Tag>, ProviderError>;
+}
+
+impl ProviderKind {
+    pub fn default_api_url(self) -> Option<&'static str> {
+        match self {
+            ProviderKind::GitHub => Some(github::GITHUB_API_URL),
+            ProviderKind::GitLab => Some(gitlab::GITLAB_API_URL),
+            ProviderKind::Gitea => Some(gitea::GITEA_API_URL),
+            ProviderKind::Bitbucket => Some(bitbucket::BITBUCKET_API_URL),
+            ProviderKind::Git => None,
+        }
+    }
+
+    // Base of the provider's public web URLs, used to build clone URLs.
+    pub fn web_url(self) -> Option<&'static str> {
+        match self {
+            ProviderKind::GitHub => Some("https://github.com"),
+            ProviderKind::GitLab => Some("https://gitlab.com"),
+            ProviderKind::Gitea => Some("https://gitea.com"),
+            ProviderKind::Bitbucket => Some("https://bitbucket.org"),
+            ProviderKind::Git => None,
+        }
+    }
+
+    // Username to pair with an access token for HTTPS git operations.
+    pub fn token_username(self) -> &'static str {
+        match self {
+            ProviderKind::GitHub => "x-access-token",
+            ProviderKind::GitLab => "oauth2",
+            ProviderKind::Gitea => "oauth2",
+            ProviderKind::Bitbucket => "x-token-auth",
+            ProviderKind::Git => "git",
+        }
+    }
+}
+
+// Owner, repo, credentials and API location shared by every provider implementation.
+struct RepoApi {
+    client: Client,
+    base_url: Url,
+    owner: String,
+    repo: String,
+    access_token: Option<String>,
+    cache: Arc<ResponseCache>,
+}
+
+impl RepoApi {
+    fn new(
+        config: &GitHubConfig,
+        client: Client,
+        cache: Arc<ResponseCache>,
+    ) -> Result<Self, String> {
+        Ok(RepoApi {
+            client,
+            base_url: config.api_base_url()?,
+            owner: config.owner.clone(),
+            repo: config.repo.clone(),
+            access_token: config.access_token.clone(),
+            cache,
+        })
+    }
+
+    // Build a GET request for the given path segments under the API root. Each segment is
+    // percent-encoded on its own, so branch names containing `/` stay a single segment.
+    fn get(&self, segments: &[&str]) -> RequestBuilder {
+        let mut url = self.base_url.clone();
+        // `api_base_url` only hands out URLs that can be a base, so this always succeeds.
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(segments);
+        }
+        self.client.get(url)
+    }
+
+    // Like `get_json`, but sends the ETag or Last-Modified of the previous response back as a
+    // conditional request. A 304 reuses the cached body, which GitHub does not count against
+    // the rate limit.
+    async fn get_json_cached<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<T, ProviderError> {
+        let mut request = request.build()?;
+        let url = request.url().to_string();
+        let cached = self.cache.get(&url);
+        if let Some(cached) = &cached {
+            let headers = request.headers_mut();
+            if let Some(etag) = cached.etag.as_ref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_NONE_MATCH, etag);
+            } else if let Some(date) = cached.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_MODIFIED_SINCE, date);
+            }
+        }
+
+        let response = rate_limit::check(self.client.execute(request).await?)?;
+
+        let body = match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some(cached)) => {
+                info!("Remote unchanged since the last check (304 Not Modified).");
+                cached.body
+            }
+            (status, _) if !status.is_success() => {
+                return Err(ProviderError::from_response(response).await);
+            }
+            (_, _) => {
+                let header = |name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_string)
+                };
+                let etag = header(ETAG);
+                let last_modified = header(LAST_MODIFIED);
+                let body = response.text().await?;
+                if etag.is_some() || last_modified.is_some() {
+                    self.cache.insert(
+                        url,
+                        CachedResponse {
+                            etag,
+                            last_modified,
+                            body: body.clone(),
+                        },
+                    );
+                }
+                body
+            }
+        };
+
+        serde_json::from_str(&body).map_err(|e| ProviderError::InvalidResponse(e.to_string()))
+    }
+}
+
+// Build the provider configured for a repository.
+pub fn from_config(
+    config: &RepoConfig,
+    client: Client,
+    cache: Arc<ResponseCache>,
+) -> Result<Box<dyn RemoteProvider>, String> {
+    if config.github.provider == ProviderKind::Git {
+        return Ok(Box::new(GitRemote::new(config.clone())));
+    }
+
+    let api = RepoApi::new(&config.github, client, cache)?;
+    Ok(match config.github.provider {
+        ProviderKind::GitHub => Box::new(GitHub::new(api)),
+        ProviderKind::GitLab => Box::new(GitLab::new(api)),
+        ProviderKind::Gitea => Box::new(Gitea::new(api)),
+        ProviderKind::Bitbucket => Box::new(Bitbucket::new(api)),
+        ProviderKind::Git => unreachable!(),
+    })
+}
+
+// Send a request and decode the JSON body, turning error statuses into typed errors.
+async fn get_json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ProviderError> {
+    let response = rate_limit::check(request.send().await?)?;
+    if !response.status().is_success() {
+        return Err(ProviderError::from_response(response).await);
+    }
+    Ok(response.json::<T>().await?)
+}
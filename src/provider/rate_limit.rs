@@ -0,0 +1,149 @@
+use super::ProviderError;
+use chrono::{TimeZone, Utc};
+use log::warn;
+use reqwest::{Response, StatusCode};
+use std::time::Duration;
+
+// Warn once less than 1/LOW_QUOTA_FRACTION of the quota is left.
+const LOW_QUOTA_FRACTION: u64 = 10;
+
+// Wait this long when a provider rate limits us without saying for how long.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// First of `names` present on the response, as a number. GitHub and Gitea use the
+// `X-RateLimit-*` headers, GitLab the unprefixed `RateLimit-*` ones.
+fn header_u64(response: &Response, names: &[&str]) -> Option<u64> {
+    names.iter().find_map(|name| {
+        response
+            .headers()
+            .get(*name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+    })
+}
+
+// Time left until the Unix timestamp `reset`, at least one second.
+fn until(reset: u64) -> Duration {
+    let now = Utc::now().timestamp().max(0) as u64;
+    Duration::from_secs(reset.saturating_sub(now).max(1))
+}
+
+// Check the rate limit headers on an API response. Warns when the remaining quota runs low,
+// and turns a rate-limited response into an error saying how long to wait, instead of letting
+// it fail as an unparseable body.
+pub fn check(response: Response) -> Result<Response, ProviderError> {
+    let remaining = header_u64(&response, &["x-ratelimit-remaining", "ratelimit-remaining"]);
+    let limit = header_u64(&response, &["x-ratelimit-limit", "ratelimit-limit"]);
+    let reset = header_u64(&response, &["x-ratelimit-reset", "ratelimit-reset"]);
+    let retry_after = header_u64(&response, &["retry-after"]);
+
+    if let Some(remaining) = remaining {
+        let low = match limit {
+            Some(limit) => remaining * LOW_QUOTA_FRACTION <= limit,
+            None => remaining <= LOW_QUOTA_FRACTION,
+        };
+        if low {
+            let resets_at = reset
+                .and_then(|reset| Utc.timestamp_opt(reset as i64, 0).single())
+                .map(|time| format!(", resets at {} UTC", time.format("%Y-%m-%d %H:%M:%S")))
+                .unwrap_or_default();
+            warn!(
+                "API quota running low: {} request(s) remaining{}",
+                remaining, resets_at
+            );
+        }
+    }
+
+    let status = response.status();
+    let rate_limited = status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN && (remaining == Some(0) || retry_after.is_some()));
+    if rate_limited {
+        let retry_after = retry_after
+            .map(Duration::from_secs)
+            .or_else(|| reset.map(until))
+            .unwrap_or(DEFAULT_RETRY_AFTER);
+        return Err(ProviderError::RateLimited { retry_after });
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, headers: &[(&str, &str)]) -> Response {
+        let mut builder = hyper::Response::builder().status(status);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        Response::from(builder.body("").unwrap())
+    }
+
+    fn retry_after(result: Result<Response, ProviderError>) -> Duration {
+        match result {
+            Err(ProviderError::RateLimited { retry_after }) => retry_after,
+            Err(e) => panic!("expected a rate limit error, got {}", e),
+            Ok(response) => panic!("expected a rate limit error, got {}", response.status()),
+        }
+    }
+
+    #[test]
+    fn passes_through_responses_with_quota_left() {
+        let result = check(response(
+            200,
+            &[
+                ("x-ratelimit-remaining", "4000"),
+                ("x-ratelimit-limit", "5000"),
+            ],
+        ));
+        assert_eq!(result.unwrap().status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn plain_forbidden_is_not_a_rate_limit() {
+        let result = check(response(403, &[("x-ratelimit-remaining", "12")]));
+        assert_eq!(result.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn too_many_requests_honors_retry_after() {
+        let result = check(response(429, &[("retry-after", "30")]));
+        assert_eq!(retry_after(result), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn exhausted_quota_waits_until_the_reset() {
+        let reset = (Utc::now().timestamp() + 120).to_string();
+        let result = check(response(
+            403,
+            &[
+                ("x-ratelimit-remaining", "0"),
+                ("x-ratelimit-reset", &reset),
+            ],
+        ));
+        let wait = retry_after(result);
+        assert!(wait > Duration::from_secs(110) && wait <= Duration::from_secs(120));
+    }
+
+    #[test]
+    fn reads_gitlab_headers() {
+        let reset = (Utc::now().timestamp() + 60).to_string();
+        let result = check(response(
+            429,
+            &[("ratelimit-remaining", "0"), ("ratelimit-reset", &reset)],
+        ));
+        assert!(retry_after(result) <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn falls_back_to_a_default_wait() {
+        let result = check(response(429, &[]));
+        assert_eq!(retry_after(result), DEFAULT_RETRY_AFTER);
+    }
+
+    #[test]
+    fn reset_in_the_past_waits_at_least_a_second() {
+        assert_eq!(until(0), Duration::from_secs(1));
+    }
+}
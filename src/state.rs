@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// What a repository task remembers across restarts. Each repo has its own file in the state
+// directory, rewritten after every cycle.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SyncState {
+    // The commit the checkout was last synced to or found up to date at.
+    pub last_synced_sha: Option<String>,
+    // When the checkout last changed, or was first found up to date.
+    pub last_sync_time: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    // Remote commit that failed its health check and was rolled back.
+    pub bad_commit: Option<String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+// The checkout at `path` in a form that is the same however it is spelled, so `./repo` and
+// `repo` are recognized as one checkout.
+pub fn canonical_checkout(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    fs::canonicalize(path)
+        .or_else(|_| {
+            // A checkout that is about to be cloned does not exist yet, but its parent does
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+            let dir = fs::canonicalize(parent.unwrap_or(Path::new(".")))?;
+            Ok::<_, std::io::Error>(dir.join(path.file_name().unwrap_or_default()))
+        })
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+// State file name for the checkout at `path`. Checkouts are what tell repos apart: two entries
+// can sync the same repo and branch into different directories. The canonical path is hashed
+// so the name is short and safe on every platform; the directory name is kept in front to make
+// the file recognizable.
+fn file_name(path: &str) -> String {
+    let canonical = canonical_checkout(path);
+    let hash = hex::encode(Sha256::digest(canonical.to_string_lossy().as_bytes()));
+    let stem: String = canonical
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("{}-{}.json", stem, &hash[..12])
+}
+
+impl SyncState {
+    // Load the state of the checkout at `checkout`, starting fresh if there is none or it is
+    // unreadable.
+    pub fn load(dir: &Path, checkout: &str) -> Self {
+        let path = dir.join(file_name(checkout));
+        let mut state: SyncState = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable state file {}: {}", path.display(), e);
+                SyncState::default()
+            }),
+            Err(_) => SyncState::default(),
+        };
+        state.path = path;
+        state
+    }
+
+    // A cycle finished without errors.
+    pub fn record_success(&mut self) {
+        self.last_error = None;
+        self.consecutive_failures = 0;
+    }
+
+    // The checkout was found at `sha`, matching the remote. The first time a repo is seen this
+    // also starts the "no new changes since" clock, so it survives restarts from then on.
+    pub fn record_up_to_date(&mut self, sha: &str) {
+        self.last_synced_sha = Some(sha.to_string());
+        self.last_sync_time.get_or_insert_with(Utc::now);
+        self.record_success();
+    }
+
+    // The checkout was just moved to `sha`. A commit skipped after a failed health check is
+    // forgotten once a different one has been synced.
+    pub fn record_sync(&mut self, sha: &str) {
+        self.last_sync_time = Some(Utc::now());
+        if self.bad_commit.as_deref() != Some(sha) {
+            self.bad_commit = None;
+        }
+        self.record_up_to_date(sha);
+    }
+
+    pub fn record_failure(&mut self, message: String) {
+        self.last_error = Some(message);
+        self.consecutive_failures += 1;
+    }
+
+    // Write to a temporary file and rename it over the old one, so a crash mid-write never
+    // leaves a truncated state file behind.
+    pub fn save(&self) {
+        let result = (|| -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let content = serde_json::to_string_pretty(self)?;
+            let tmp_path = self.path.with_extension("tmp");
+            fs::write(&tmp_path, content)?;
+            fs::rename(&tmp_path, &self.path)
+        })();
+        if let Err(e) = result {
+            error!("Failed to write state file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_the_bad_commit_after_a_newer_sync() {
+        let mut state = SyncState {
+            bad_commit: Some("bad".to_string()),
+            ..SyncState::default()
+        };
+        state.record_up_to_date("old");
+        assert_eq!(state.bad_commit.as_deref(), Some("bad"));
+        state.record_sync("new");
+        assert_eq!(state.bad_commit, None);
+        assert_eq!(state.last_synced_sha.as_deref(), Some("new"));
+    }
+}
@@ -0,0 +1,662 @@
+use crate::config::{Config, DirtyPolicy, RepoConfig, SyncStrategy};
+use crate::git::{self, BranchState};
+use crate::history::{History, SyncRecord, SyncResult};
+use crate::hooks;
+use crate::provider::{self, ProviderError, RemoteProvider, ResponseCache};
+use crate::state::SyncState;
+use crate::webhook;
+use chrono::{DateTime, Utc};
+use git2::Repository;
+use log::{error, info, warn};
+use reqwest::Client;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+
+// Utility function for formatting the time in a consistent format.
+pub fn format_time(time: SystemTime) -> String {
+    let datetime: DateTime<Utc> = time.into();
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+// Exponential backoff function to avoid hammering GitHub with too many requests in case of errors.
+fn exponential_backoff(attempt: u32) -> Duration {
+    let delay = 2u64.pow(attempt.min(6)); // Cap the delay to 64 seconds (2^6)
+    Duration::from_secs(delay)
+}
+
+// Get the local commit SHA from the local Git repository.
+fn get_local_commit_sha(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    let commit = head.peel_to_commit().ok()?;
+    let local_commit = commit.id().to_string();
+    info!("Fetched local commit: {}", local_commit);
+    Some(local_commit)
+}
+
+// Log what the provider reports about the commits about to be pulled. Purely informational,
+// so a failed API call here never holds up the sync.
+async fn log_incoming_changes(
+    name: &str,
+    provider: &dyn RemoteProvider,
+    local_commit: &str,
+    remote_commit: &str,
+) {
+    match provider.compare(local_commit, remote_commit).await {
+        Ok(comparison) => {
+            let mut authors: Vec<&str> = comparison
+                .commits
+                .iter()
+                .map(|c| c.author.as_str())
+                .collect();
+            authors.sort_unstable();
+            authors.dedup();
+            info!(
+                "[{}] Incoming: {} new commit(s) by {}",
+                name,
+                comparison.ahead_by,
+                authors.join(", ")
+            );
+            for commit in &comparison.commits {
+                info!("[{}]   {} by {}", name, commit.sha, commit.author);
+            }
+            if comparison.behind_by > 0 {
+                info!(
+                    "[{}] The remote is missing {} local commit(s).",
+                    name, comparison.behind_by
+                );
+            }
+        }
+        Err(ProviderError::Unsupported(_)) => {}
+        Err(e) => warn!("[{}] Could not list incoming commits: {}", name, e),
+    }
+
+    match provider.list_tags().await {
+        Ok(tags) => {
+            for tag in tags.iter().filter(|t| t.sha == remote_commit) {
+                info!("[{}] Incoming commit is tagged {}", name, tag.name);
+            }
+        }
+        Err(ProviderError::Unsupported(_)) => {}
+        Err(e) => warn!("[{}] Could not list tags: {}", name, e),
+    }
+}
+
+// Describe a move of the checkout from `from` to `to` for hook commands.
+fn hook_context(
+    name: &str,
+    config: &RepoConfig,
+    repo: &Repository,
+    from: &str,
+    to: &str,
+) -> hooks::HookContext {
+    let changed_files = git::changed_files(repo, from, to).unwrap_or_else(|e| {
+        warn!("[{}] Failed to list changed files for hooks: {}", name, e);
+        Vec::new()
+    });
+    hooks::HookContext {
+        old_sha: from.to_string(),
+        new_sha: to.to_string(),
+        branch: config.github.target_branch.clone(),
+        changed_files,
+    }
+}
+
+// Describe a sync for the post-sync hooks, or None if the checkout did not move or there are
+// no hooks to run.
+fn post_sync_context(
+    name: &str,
+    config: &RepoConfig,
+    repo: &Repository,
+    outcome: &git::SyncOutcome,
+) -> Option<hooks::HookContext> {
+    let (from, to) = outcome.moved().filter(|_| !config.post_sync.is_empty())?;
+    Some(hook_context(name, config, repo, from, to))
+}
+
+// Run the configured post-sync hooks in the checkout.
+async fn run_post_sync_hooks(name: &str, config: &RepoConfig, context: &hooks::HookContext) {
+    let dir = Path::new(&config.local_repo.path);
+    if !hooks::run_post_sync(name, &config.post_sync, dir, context).await {
+        error!("[{}] One or more post-sync hooks failed.", name);
+    }
+}
+
+// Run the health check after a sync moved the checkout from `from` to `to`. If it fails, roll
+// the checkout back to `from` and re-run the post-sync hooks for the rollback. Takes the repo
+// by `&mut` so the future stays `Send` across the awaits. Returns whether it rolled back.
+async fn check_health_or_roll_back(
+    name: &str,
+    config: &RepoConfig,
+    client: &Client,
+    repo: &mut Repository,
+    from: &str,
+    to: &str,
+) -> bool {
+    let check = match &config.health_check {
+        Some(check) => check,
+        None => return false,
+    };
+    let context = hook_context(name, config, repo, from, to);
+    let dir = Path::new(&config.local_repo.path);
+    let reason = match hooks::health_check(name, check, dir, &context, client).await {
+        Ok(()) => {
+            info!("[{}] Health check passed.", name);
+            return false;
+        }
+        Err(reason) => reason,
+    };
+
+    error!(
+        "[{}] Health check failed after syncing to {}: {}. Rolling back to {}.",
+        name, to, reason, from
+    );
+    let rollback = match tokio::task::block_in_place(|| git::roll_back(repo, config, from)) {
+        Ok(rollback) => rollback,
+        Err(e) => {
+            error!("[{}] Failed to roll back to {}: {}", name, from, e);
+            return false;
+        }
+    };
+    warn!("[{}] Rolled back: {}.", name, rollback);
+    if let Some(context) = post_sync_context(name, config, repo, &rollback) {
+        run_post_sync_hooks(name, config, &context).await;
+    }
+    true
+}
+
+// Print the periodic "nothing changed" status line for a repository.
+fn report_no_changes(name: &str, last_change_time: SystemTime) {
+    let elapsed = last_change_time.elapsed().unwrap_or_default().as_secs();
+    let formatted_time = format_time(last_change_time);
+    println!(
+        "[{}] No new changes since {} UTC. Elapsed time: {} seconds.",
+        name, formatted_time, elapsed
+    );
+    let _ = io::stdout().flush();
+}
+
+// Handles shared by every repository task.
+#[derive(Clone)]
+pub struct SyncContext {
+    pub client: Client,
+    // Like `client`, but without the proxy.
+    pub health_check_client: Client,
+    pub cache: Arc<ResponseCache>,
+    // Slowest polling interval while webhooks deliver pushes, if the receiver is enabled.
+    pub fallback_interval: Option<Duration>,
+    pub state_dir: PathBuf,
+    pub history: Arc<History>,
+}
+
+impl SyncContext {
+    // Build the shared handles from the top-level settings of `config`.
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let client = provider::build_client(&config.http)
+            .map_err(|e| format!("Invalid [http] configuration: {}", e))?;
+        let health_check_client = provider::build_health_check_client(&config.http)
+            .map_err(|e| format!("Invalid [http] configuration: {}", e))?;
+        Ok(SyncContext {
+            client,
+            health_check_client,
+            cache: Arc::new(ResponseCache::load(&config.cache_file)),
+            fallback_interval: config
+                .webhook
+                .as_ref()
+                .map(|webhook| Duration::from_secs(webhook.fallback_interval_seconds)),
+            state_dir: PathBuf::from(&config.state_dir),
+            history: Arc::new(History::new(&config.history_file)),
+        })
+    }
+}
+
+// When to try again after a failed check.
+pub enum Retry {
+    // At the next regular check.
+    NextCheck,
+    // After an exponential backoff based on the consecutive failures.
+    BackOff,
+    // After a fixed delay, e.g. until the API rate limit resets.
+    After(Duration),
+    // Never: retrying cannot fix it without a configuration change.
+    Never,
+}
+
+// How a single check of a repository ended.
+pub enum Cycle {
+    UpToDate,
+    Synced,
+    // There were changes, but they were deliberately not pulled this time.
+    Skipped,
+    Failed(Retry),
+}
+
+// One configured repository, with everything it remembers between checks.
+pub struct RepoSync {
+    pub name: String,
+    config: RepoConfig,
+    provider: Box<dyn RemoteProvider>,
+    health_check_client: Client,
+    history: Arc<History>,
+    state: SyncState,
+    last_change_time: SystemTime,
+    last_state: Option<BranchState>,
+    // Local edits that made the last check skip under the `refuse` policy, so they are only
+    // logged when they change.
+    dirty_paths: Vec<String>,
+}
+
+impl RepoSync {
+    pub fn new(config: RepoConfig, context: &SyncContext) -> Result<Self, String> {
+        let name = config.name();
+        let provider =
+            provider::from_config(&config, context.client.clone(), context.cache.clone())
+                .map_err(|e| format!("[{}] {}", name, e))?;
+        let state = SyncState::load(&context.state_dir, &config.local_repo.path);
+        let last_change_time = state
+            .last_sync_time
+            .map(SystemTime::from)
+            .unwrap_or_else(SystemTime::now);
+        Ok(RepoSync {
+            name,
+            config,
+            provider,
+            health_check_client: context.health_check_client.clone(),
+            history: context.history.clone(),
+            state,
+            last_change_time,
+            last_state: None,
+            dirty_paths: Vec::new(),
+        })
+    }
+
+    // Log a failed step and record it in the repo's state.
+    fn fail(&mut self, message: String, retry: Retry) -> Cycle {
+        error!("[{}] {}", self.name, message);
+        self.state.record_failure(message);
+        Cycle::Failed(retry)
+    }
+
+    // Check the repository once and pull the remote changes if there are any. The state is
+    // updated but not saved, see `save_state`.
+    pub async fn check(&mut self) -> Cycle {
+        let name = self.name.clone();
+        let config = &self.config;
+
+        let mut repo = match Repository::open(&config.local_repo.path) {
+            Ok(repo) => repo,
+            Err(_)
+                if config.local_repo.clone_if_missing
+                    && git::is_missing_checkout(&config.local_repo.path) =>
+            {
+                match tokio::task::block_in_place(|| git::clone_repo(config)) {
+                    Ok(repo) => {
+                        info!(
+                            "[{}] Cloned repository into {}",
+                            name, config.local_repo.path
+                        );
+                        self.last_change_time = SystemTime::now();
+                        if let Some(sha) = get_local_commit_sha(&repo) {
+                            self.state.record_sync(&sha);
+                        }
+                        repo
+                    }
+                    Err(e) => {
+                        return self
+                            .fail(format!("Failed to clone repository: {}", e), Retry::BackOff);
+                    }
+                }
+            }
+            Err(e) => {
+                return self.fail(
+                    format!("Failed to open local repository: {}", e),
+                    Retry::NextCheck,
+                );
+            }
+        };
+
+        // Never compare or pull into anything other than the target branch
+        match tokio::task::block_in_place(|| git::ensure_target_branch(&repo, config)) {
+            Ok(()) => {}
+            Err(e @ git::SyncError::WrongBranch { .. }) => {
+                warn!("[{}] {}", name, e);
+                return Cycle::Skipped;
+            }
+            Err(e) => {
+                return self.fail(
+                    format!("Failed to switch to the target branch: {}", e),
+                    Retry::BackOff,
+                );
+            }
+        }
+
+        let latest_remote_commit = match self
+            .provider
+            .latest_commit_sha(&config.github.target_branch)
+            .await
+        {
+            Ok(commit) => commit,
+            // Wait for the quota to reset rather than following the blind backoff
+            Err(ProviderError::RateLimited { retry_after }) => {
+                warn!(
+                    "[{}] API rate limit exceeded. Waiting {} seconds for it to reset.",
+                    name,
+                    retry_after.as_secs()
+                );
+                return Cycle::Failed(Retry::After(retry_after));
+            }
+            // Retrying will not fix a bad token or a wrong repo, so stop instead of backing off
+            Err(e) if e.is_permanent() => {
+                error!(
+                    "[{}] Failed to get latest remote commit: {}. Stopping sync for this repository until the configuration is fixed.",
+                    name, e
+                );
+                self.state
+                    .record_failure(format!("Failed to get latest remote commit: {}", e));
+                return Cycle::Failed(Retry::Never);
+            }
+            Err(e @ ProviderError::EmptyRepository(_)) => {
+                warn!("[{}] {}. Waiting for the first push.", name, e);
+                return Cycle::Skipped;
+            }
+            Err(e) => {
+                return self.fail(
+                    format!("Failed to get latest remote commit: {}", e),
+                    Retry::BackOff,
+                );
+            }
+        };
+
+        let local_commit = match get_local_commit_sha(&repo) {
+            Some(commit) => commit,
+            None => return self.fail("Failed to get local commit.".to_string(), Retry::BackOff),
+        };
+
+        // Nothing to fetch when HEAD already matches the remote tip
+        if latest_remote_commit == local_commit {
+            self.state.record_up_to_date(&local_commit);
+            report_no_changes(&name, self.last_change_time);
+            self.last_state = Some(BranchState::UpToDate);
+            return Cycle::UpToDate;
+        }
+
+        // Skip a commit that failed its health check until a newer one arrives
+        if self.state.bad_commit.as_deref() == Some(latest_remote_commit.as_str()) {
+            report_no_changes(&name, self.last_change_time);
+            return Cycle::Skipped;
+        }
+
+        // Bring the remote-tracking ref up to date and work out how the branches relate
+        let (state, fetched_commit) = match tokio::task::block_in_place(|| {
+            git::fetch_if_stale(&repo, config, &latest_remote_commit)?;
+            Ok::<_, git::SyncError>((
+                git::branch_state(&repo, config)?,
+                git::fetched_sha(&repo, config)?,
+            ))
+        }) {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                return self.fail(
+                    format!("Failed to compare local and remote branches: {}", e),
+                    Retry::BackOff,
+                );
+            }
+        };
+        // A push may have landed since the API was asked. What was fetched is what gets applied,
+        // so that is the commit the gates see, the history records and a failed health check
+        // marks as bad.
+        if fetched_commit != latest_remote_commit {
+            info!(
+                "[{}] The remote moved on to {} since the API reported {}.",
+                name, fetched_commit, latest_remote_commit
+            );
+            if self.state.bad_commit.as_deref() == Some(fetched_commit.as_str()) {
+                report_no_changes(&name, self.last_change_time);
+                return Cycle::Skipped;
+            }
+        }
+        let latest_remote_commit = fetched_commit;
+        let state_changed = self.last_state != Some(state);
+        self.last_state = Some(state);
+
+        match state {
+            BranchState::UpToDate => {
+                self.state.record_up_to_date(&local_commit);
+                report_no_changes(&name, self.last_change_time);
+                return Cycle::UpToDate;
+            }
+            BranchState::Ahead(_) if config.local_repo.strategy != SyncStrategy::ResetHard => {
+                if state_changed {
+                    info!("[{}] Local branch is {}. Nothing to pull.", name, state);
+                }
+                self.state.record_success();
+                report_no_changes(&name, self.last_change_time);
+                return Cycle::UpToDate;
+            }
+            BranchState::Diverged { .. } if config.local_repo.strategy == SyncStrategy::FfOnly => {
+                if state_changed {
+                    warn!(
+                        "[{}] Local branch has {}. The ff-only strategy will not update it.",
+                        name, state
+                    );
+                }
+                return Cycle::Skipped;
+            }
+            _ => {}
+        }
+
+        // Leave local edits alone under the refuse policy: skip until they are gone
+        if config.local_repo.dirty_policy == DirtyPolicy::Refuse {
+            let paths = match tokio::task::block_in_place(|| git::dirty_paths(&repo)) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    return self.fail(
+                        format!("Failed to check the working tree for local changes: {}", e),
+                        Retry::BackOff,
+                    );
+                }
+            };
+            if !paths.is_empty() {
+                if paths != self.dirty_paths {
+                    warn!(
+                        "[{}] {}",
+                        name,
+                        git::SyncError::DirtyWorkTree(paths.clone())
+                    );
+                    self.dirty_paths = paths;
+                }
+                return Cycle::Skipped;
+            }
+            self.dirty_paths.clear();
+        }
+
+        info!("[{}] Local branch is {}. Pulling updates...", name, state);
+        log_incoming_changes(
+            &name,
+            self.provider.as_ref(),
+            &local_commit,
+            &latest_remote_commit,
+        )
+        .await;
+        // Give the pre-sync gates a chance to veto the update before anything changes
+        if !config.pre_sync.is_empty() {
+            let hook_context =
+                hook_context(&name, config, &repo, &local_commit, &latest_remote_commit);
+            let dir = Path::new(&config.local_repo.path);
+            if let Err(reason) =
+                hooks::run_pre_sync(&name, &config.pre_sync, dir, &hook_context).await
+            {
+                warn!(
+                    "[{}] Skipping update to {}: {}.",
+                    name, latest_remote_commit, reason
+                );
+                return Cycle::Skipped;
+            }
+        }
+
+        let started = Instant::now();
+        let mut authors =
+            git::commit_authors(&repo, &local_commit, &latest_remote_commit).unwrap_or_default();
+        let commits = authors.len();
+        authors.sort_unstable();
+        authors.dedup();
+        let mut record = SyncRecord {
+            timestamp: Utc::now(),
+            repo: name.clone(),
+            path: config.local_repo.path.clone(),
+            old_sha: local_commit.clone(),
+            new_sha: latest_remote_commit.clone(),
+            commits,
+            authors,
+            strategy: config.local_repo.strategy.to_string(),
+            duration_ms: 0,
+            result: SyncResult::Synced,
+            detail: String::new(),
+        };
+        let outcome = match tokio::task::block_in_place(|| {
+            git::pull_latest_changes(&mut repo, config, state)
+        }) {
+            Ok(outcome) => outcome,
+            // Local edits appeared since the check above; skip like it would have
+            Err(e @ git::SyncError::DirtyWorkTree(_)) => {
+                warn!("[{}] {}", name, e);
+                return Cycle::Skipped;
+            }
+            Err(e) => {
+                record.result = SyncResult::Failed;
+                record.detail = e.to_string();
+                record.duration_ms = started.elapsed().as_millis() as u64;
+                self.history.append(&record);
+                return self.fail(
+                    format!("Failed to pull latest changes: {}", e),
+                    Retry::BackOff,
+                );
+            }
+        };
+
+        record.detail = outcome.to_string();
+        info!(
+            "[{}] Successfully pulled latest changes: {}.",
+            name, outcome
+        );
+        self.last_change_time = SystemTime::now();
+        self.last_state = Some(BranchState::UpToDate);
+        match outcome.moved() {
+            Some((_, to)) => {
+                record.new_sha = to.to_string();
+                self.state.record_sync(to);
+            }
+            None => self.state.record_success(),
+        }
+        if let Some(hook_context) = post_sync_context(&name, config, &repo, &outcome) {
+            run_post_sync_hooks(&name, config, &hook_context).await;
+        }
+
+        let mut cycle = Cycle::Synced;
+        if let Some((from, to)) = outcome.moved() {
+            if check_health_or_roll_back(
+                &name,
+                config,
+                &self.health_check_client,
+                &mut repo,
+                from,
+                to,
+            )
+            .await
+            {
+                warn!(
+                    "[{}] {} will not be pulled again until a newer commit arrives.",
+                    name, latest_remote_commit
+                );
+                self.state.bad_commit = Some(latest_remote_commit.clone());
+                self.state
+                    .record_failure(format!("Health check failed for {}", latest_remote_commit));
+                if let Some(sha) = get_local_commit_sha(&repo) {
+                    self.state.last_synced_sha = Some(sha);
+                }
+                record.result = SyncResult::RolledBack;
+                record.detail = format!(
+                    "{}, then rolled back after a failed health check",
+                    record.detail
+                );
+                cycle = Cycle::Failed(Retry::NextCheck);
+            }
+        }
+        record.duration_ms = started.elapsed().as_millis() as u64;
+        self.history.append(&record);
+        cycle
+    }
+
+    // Write the repo's state file, after each check.
+    pub fn save_state(&self) {
+        self.state.save();
+    }
+
+    // How long to wait before the next check after `cycle`, or None to stop checking.
+    fn next_check_in(&self, cycle: &Cycle, check_interval: Duration) -> Option<Duration> {
+        match cycle {
+            Cycle::UpToDate | Cycle::Synced | Cycle::Skipped => Some(check_interval),
+            Cycle::Failed(Retry::NextCheck) => Some(check_interval),
+            // The failure just recorded is not part of the delay before the first retry
+            Cycle::Failed(Retry::BackOff) => Some(exponential_backoff(
+                self.state.consecutive_failures.saturating_sub(1),
+            )),
+            Cycle::Failed(Retry::After(delay)) => Some(*delay),
+            Cycle::Failed(Retry::Never) => None,
+        }
+    }
+}
+
+// Polling loop for a single repository. Each configured repo runs one of these as its own
+// task, so backoff and last-change tracking are kept per repo and persisted in its state file.
+// `stop` ends the loop once the current check is finished, e.g. when the repo is removed from
+// the config.
+pub async fn run_repo(
+    config: RepoConfig,
+    context: SyncContext,
+    trigger: Arc<Notify>,
+    stop: Arc<Notify>,
+) {
+    let mut check_interval = Duration::from_secs(config.local_repo.check_interval_seconds);
+    // The receiver only understands GitHub push events, so other providers keep their interval
+    if let Some(fallback_interval) = context
+        .fallback_interval
+        .filter(|_| webhook::can_trigger(&config))
+    {
+        check_interval = check_interval.max(fallback_interval);
+    }
+    let path = config.local_repo.path.clone();
+    let mut repo = match RepoSync::new(config, &context) {
+        Ok(repo) => repo,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    info!("[{}] Watching {}", repo.name, path);
+
+    loop {
+        let cycle = repo.check().await;
+        repo.save_state();
+        let delay = match repo.next_check_in(&cycle, check_interval) {
+            Some(delay) => delay,
+            None => return,
+        };
+        // Only the regular interval can be cut short by a webhook
+        let wakes_on_webhook = !matches!(cycle, Cycle::Failed(Retry::BackOff | Retry::After(_)));
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = trigger.notified(), if wakes_on_webhook => {
+                info!("[{}] Sync triggered by webhook.", repo.name)
+            }
+            _ = stop.notified() => {
+                info!("[{}] Stopped watching {}", repo.name, path);
+                return;
+            }
+        }
+    }
+}
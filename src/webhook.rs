@@ -0,0 +1,264 @@
+use crate::config::{ProviderKind, RepoConfig, WebhookConfig};
+use hmac::{Hmac, Mac};
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info, warn};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+
+// A repository the webhook can wake up, and the handle its polling task waits on.
+pub struct Trigger {
+    full_name: String,
+    branch_ref: String,
+    notify: Arc<Notify>,
+}
+
+// Whether a GitHub push event can ever wake the repo up, i.e. whether it is hosted on GitHub.
+pub fn can_trigger(config: &RepoConfig) -> bool {
+    config.github.provider == ProviderKind::GitHub
+}
+
+impl Trigger {
+    pub fn new(config: &RepoConfig, notify: Arc<Notify>) -> Self {
+        Trigger {
+            full_name: format!("{}/{}", config.github.owner, config.github.repo),
+            branch_ref: format!("refs/heads/{}", config.github.target_branch),
+            notify,
+        }
+    }
+}
+
+// The repositories currently being synced. Replaced whenever the config is reloaded, so the
+// receiver can keep running across reloads.
+pub type Triggers = Arc<RwLock<Vec<Trigger>>>;
+
+// The parts of a GitHub `push` event payload used to pick the repositories to sync.
+#[derive(Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    after: String,
+    repository: PushRepository,
+}
+
+#[derive(Deserialize)]
+struct PushRepository {
+    full_name: String,
+}
+
+// Largest payload GitHub delivers. Anything bigger is refused before it is read, since the
+// signature can only be checked once the whole body is in memory.
+const MAX_BODY_BYTES: usize = 25 * 1024 * 1024;
+
+struct Receiver {
+    config: WebhookConfig,
+    triggers: Triggers,
+}
+
+// Check `X-Hub-Signature-256`, the HMAC-SHA256 of the body keyed with the shared secret.
+fn verify_signature(secret: &str, body: &[u8], header: Option<&str>) -> bool {
+    let signature = match header
+        .and_then(|h| h.strip_prefix("sha256="))
+        .and_then(|h| hex::decode(h).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+// Why a request body was not read.
+enum BodyError {
+    TooLarge,
+    Read(hyper::Error),
+}
+
+// Read the whole body, giving up as soon as it exceeds `MAX_BODY_BYTES`.
+async fn read_body(mut body: Body) -> Result<Vec<u8>, BodyError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(BodyError::Read)?;
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(BodyError::TooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn respond(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(format!("{}\n", message)));
+    *response.status_mut() = status;
+    response
+}
+
+async fn handle(receiver: Arc<Receiver>, request: Request<Body>) -> Response<Body> {
+    if request.uri().path() != receiver.config.path {
+        return respond(StatusCode::NOT_FOUND, "not found");
+    }
+    if request.method() != Method::POST {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let event = header("X-GitHub-Event").unwrap_or_default();
+    let signature = header("X-Hub-Signature-256");
+    let content_length = header("Content-Length").and_then(|v| v.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > MAX_BODY_BYTES) {
+        warn!(
+            "Rejected webhook with a body over {} bytes.",
+            MAX_BODY_BYTES
+        );
+        return respond(StatusCode::PAYLOAD_TOO_LARGE, "payload too large");
+    }
+    let body = match read_body(request.into_body()).await {
+        Ok(body) => body,
+        Err(BodyError::TooLarge) => {
+            warn!(
+                "Rejected webhook with a body over {} bytes.",
+                MAX_BODY_BYTES
+            );
+            return respond(StatusCode::PAYLOAD_TOO_LARGE, "payload too large");
+        }
+        Err(BodyError::Read(e)) => {
+            warn!("Failed to read webhook body: {}", e);
+            return respond(StatusCode::BAD_REQUEST, "unreadable body");
+        }
+    };
+
+    if !verify_signature(&receiver.config.secret, &body, signature.as_deref()) {
+        warn!("Rejected webhook with a missing or invalid X-Hub-Signature-256.");
+        return respond(StatusCode::UNAUTHORIZED, "invalid signature");
+    }
+
+    match event.as_str() {
+        "ping" => respond(StatusCode::OK, "pong"),
+        "push" => {
+            let push: PushEvent = match serde_json::from_slice(&body) {
+                Ok(push) => push,
+                Err(e) => {
+                    warn!("Failed to parse push webhook: {}", e);
+                    return respond(StatusCode::BAD_REQUEST, "invalid push payload");
+                }
+            };
+
+            let matching: Vec<Arc<Notify>> = receiver
+                .triggers
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|t| {
+                    t.full_name.eq_ignore_ascii_case(&push.repository.full_name)
+                        && t.branch_ref == push.git_ref
+                })
+                .map(|t| t.notify.clone())
+                .collect();
+            if matching.is_empty() {
+                return respond(StatusCode::OK, "no matching repository");
+            }
+
+            info!(
+                "Webhook: push to {} {} ({}), triggering {} sync(s).",
+                push.repository.full_name,
+                push.git_ref,
+                push.after,
+                matching.len()
+            );
+            for notify in matching {
+                notify.notify_one();
+            }
+            respond(StatusCode::ACCEPTED, "sync triggered")
+        }
+        _ => respond(StatusCode::OK, "event ignored"),
+    }
+}
+
+// Serve the webhook endpoint until the process exits or the task is aborted.
+pub async fn serve(config: WebhookConfig, triggers: Triggers) {
+    let addr: SocketAddr = match config.listen.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("Invalid webhook listen address {}: {}", config.listen, e);
+            return;
+        }
+    };
+    let receiver = Arc::new(Receiver { config, triggers });
+
+    let make_service = make_service_fn(move |_| {
+        let receiver = receiver.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let receiver = receiver.clone();
+                async move { Ok::<_, Infallible>(handle(receiver, request).await) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!("Failed to listen for webhooks on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Listening for webhooks on {}", addr);
+    if let Err(e) = server.await {
+        error!("Webhook server stopped: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from GitHub's "Validating webhook deliveries" documentation.
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        assert!(verify_signature(SECRET, BODY, Some(SIGNATURE)));
+    }
+
+    #[test]
+    fn rejects_a_wrong_secret_or_body() {
+        assert!(!verify_signature("wrong secret", BODY, Some(SIGNATURE)));
+        assert!(!verify_signature(SECRET, b"Hello, World?", Some(SIGNATURE)));
+    }
+
+    #[test]
+    fn rejects_missing_or_malformed_signatures() {
+        assert!(!verify_signature(SECRET, BODY, None));
+        assert!(!verify_signature(SECRET, BODY, Some("sha256=")));
+        assert!(!verify_signature(SECRET, BODY, Some("sha256=not-hex")));
+        assert!(!verify_signature(SECRET, BODY, Some(&SIGNATURE[7..])));
+        let sha1 = SIGNATURE.replace("sha256=", "sha1=");
+        assert!(!verify_signature(SECRET, BODY, Some(&sha1)));
+    }
+
+    #[tokio::test]
+    async fn refuses_oversized_bodies() {
+        let body = Body::from(vec![0u8; MAX_BODY_BYTES + 1]);
+        assert!(matches!(read_body(body).await, Err(BodyError::TooLarge)));
+        let body = Body::from(vec![0u8; 1024]);
+        assert!(matches!(read_body(body).await, Ok(bytes) if bytes.len() == 1024));
+    }
+}